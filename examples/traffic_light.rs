@@ -3,7 +3,7 @@ extern crate machine;
 
 machine!(
   #[derive(Clone,Debug,PartialEq)]
-  enum Traffic {
+  pub enum Traffic {
     Green { count: u8 },
     Orange,
     Red,
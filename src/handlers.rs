@@ -0,0 +1,407 @@
+use machine::Machine;
+use std::sync::Mutex;
+use syn::{
+    Attribute, Error, FnArg, Ident, ImplItem, ImplItemMethod, Item, ItemImpl, Path, ReturnType,
+    Type, TypeReference,
+    export::TokenStream2,
+    parse::{Parse, ParseStream, Result},
+    punctuated::Punctuated,
+};
+use transitions::parse_description;
+
+#[derive(Debug)]
+pub struct Handlers {
+    pub machine_name: Ident,
+    pub transitions: Vec<TokenStream2>,
+    /// whether this is the first `#[handlers]` block of the machine
+    pub first: bool,
+}
+
+/// the `#[handlers]` blocks expanded in this compilation: the location of the
+/// machine's name, and of the name in the machine's first block
+static BLOCKS: Mutex<Vec<(Location, Location)>> = Mutex::new(Vec::new());
+
+type Location = (String, usize, usize);
+
+fn location(ident: &Ident) -> Location {
+    let span = ident.span().unwrap();
+    (span.file(), span.line(), span.column())
+}
+
+// whether `block`, the name given to `#[handlers]`, is in the first block of
+// `machine`. A block expanded again, as done by the IDEs, keeps its location
+fn first_block(machine: &Ident, block: &Ident) -> bool {
+    let machine = location(machine);
+    let block = location(block);
+    let mut blocks = BLOCKS.lock().unwrap_or_else(|e| e.into_inner());
+
+    match blocks.iter().find(|(m, _)| m == &machine) {
+        Some((_, first)) => first == &block,
+        None => {
+            blocks.push((machine, block));
+            true
+        }
+    }
+}
+
+/// `{ <machine description> } Traffic, <item>`: the input of the callback
+/// through which `#[handlers]` gets the machine's description
+impl Parse for Handlers {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let machine = parse_description(input)?;
+        let machine_name: Ident = input.parse()?;
+        let _: Token![,] = input.parse()?;
+        let item: Item = input.parse()?;
+
+        match machine {
+            Some(machine) => {
+                let mut handlers = Handlers::new(&machine, machine_name, item)?;
+                handlers.first = first_block(machine.name(), &handlers.machine_name);
+                Ok(handlers)
+            }
+            None => Err(Error::new(
+                machine_name.span(),
+                format!("the description of `{}` is missing", machine_name),
+            )),
+        }
+    }
+}
+
+/// end states listed in a `#[targets(Green, Orange)]` attribute
+struct Targets {
+    states: Vec<Ident>,
+}
+
+impl Parse for Targets {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let content;
+        parenthesized!(content in input);
+
+        let punctuated: Punctuated<Ident, Token![,]> = content.parse_terminated(Ident::parse)?;
+
+        Ok(Targets {
+            states: punctuated.into_iter().collect(),
+        })
+    }
+}
+
+impl Handlers {
+    /// infers the transition table from the `on_*` methods of an impl block,
+    /// or of all the impl blocks in an inline module
+    pub fn new(machine: &Machine, machine_name: Ident, mut item: Item) -> Result<Self> {
+        let mut transitions = Vec::new();
+
+        match &mut item {
+            Item::Impl(imp) => {
+                transitions.extend(infer_impl(machine, imp, None, &[])?);
+            }
+            Item::Mod(module) => {
+                let mod_name = module.ident.clone();
+                let items = match &mut module.content {
+                    Some((_, items)) => items,
+                    None => {
+                        return Err(Error::new(
+                            module.ident.span(),
+                            "handlers can only be inferred from an inline module",
+                        ))
+                    }
+                };
+
+                // outcome enums declared next to the handlers
+                let enums = items
+                    .iter()
+                    .filter_map(|item| match item {
+                        Item::Enum(e) => Some((
+                            e.ident.clone(),
+                            e.variants.iter().map(|v| v.ident.clone()).collect::<Vec<_>>(),
+                        )),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+
+                for item in items.iter_mut() {
+                    if let Item::Impl(imp) = item {
+                        transitions.extend(infer_impl(machine, imp, Some(&mod_name), &enums)?);
+                    }
+                }
+            }
+            _ => {
+                return Err(Error::new(
+                    machine_name.span(),
+                    "handlers must be applied to an impl block or an inline module",
+                ))
+            }
+        }
+
+        Ok(Handlers {
+            machine_name,
+            transitions,
+            first: true,
+        })
+    }
+
+    /// removes the `#[targets(...)]` helper attributes, the item being
+    /// emitted by the attribute while the inference waits for the description
+    pub fn strip_targets(item: &mut Item) {
+        let strip = |imp: &mut ItemImpl| {
+            for item in imp.items.iter_mut() {
+                if let ImplItem::Method(method) = item {
+                    method.attrs.retain(|a| !a.path.is_ident("targets"));
+                }
+            }
+        };
+
+        match item {
+            Item::Impl(imp) => strip(imp),
+            Item::Mod(module) => {
+                if let Some((_, items)) = &mut module.content {
+                    for item in items.iter_mut() {
+                        if let Item::Impl(imp) = item {
+                            strip(imp);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn generate(&self) -> syn::export::TokenStream {
+        let Handlers {
+            machine_name,
+            transitions,
+            first,
+        } = self;
+
+        // a second block would generate the messages enum and the wrappers again
+        if !first {
+            let message = format!(
+                "`{}` already has a `#[handlers]` block, its handlers must all be in one \
+                 impl block or in one inline module",
+                machine_name
+            );
+            return proc_macro::TokenStream::from(
+                quote_spanned!(machine_name.span()=> compile_error!(#message);),
+            );
+        }
+
+        let tokens = quote! {
+            ::machine::transitions!(#machine_name,
+                [
+                    #(#transitions),*
+                ]
+            );
+        };
+
+        proc_macro::TokenStream::from(tokens)
+    }
+}
+
+fn infer_impl(
+    machine: &Machine,
+    imp: &mut ItemImpl,
+    mod_name: Option<&Ident>,
+    enums: &[(Ident, Vec<Ident>)],
+) -> Result<Vec<TokenStream2>> {
+    if imp.trait_.is_some() {
+        return Ok(Vec::new());
+    }
+
+    let state = match type_ident(&imp.self_ty) {
+        Some(state) => state.clone(),
+        None => {
+            return Err(Error::new_spanned(
+                &imp.self_ty,
+                "handlers must be implemented directly on a state",
+            ))
+        }
+    };
+
+    let mut transitions = Vec::new();
+
+    for item in imp.items.iter_mut() {
+        if let ImplItem::Method(method) = item {
+            let prefix = &machine.naming().handler_prefix;
+            if method.sig.ident.to_string().starts_with(prefix.as_str()) {
+                transitions.push(infer_method(machine, &state, method, mod_name, enums)?);
+            }
+        }
+    }
+
+    Ok(transitions)
+}
+
+fn infer_method(
+    machine: &Machine,
+    state: &Ident,
+    method: &mut ImplItemMethod,
+    mod_name: Option<&Ident>,
+    enums: &[(Ident, Vec<Ident>)],
+) -> Result<TokenStream2> {
+    let machine_name = machine.name();
+    let ident = &method.sig.ident;
+
+    // the context is passed by reference before the message, which is passed by value
+    let mut messages = Vec::new();
+    for arg in method.sig.decl.inputs.iter() {
+        if let FnArg::Captured(a) = arg {
+            match &a.ty {
+                Type::Reference(reference) => {
+                    check_context(machine, reference)?;
+                    if !messages.is_empty() {
+                        return Err(Error::new_spanned(
+                            reference,
+                            "the context must come before the message",
+                        ));
+                    }
+                }
+                ty => messages.push(ty),
+            }
+        }
+    }
+
+    // `on_complete(self)` handles the state's completion transition
+    let naming = machine.naming();
+    let trigger = match messages.as_slice() {
+        [] if ident == &naming.handler_suffix("complete") => quote!(_),
+        [ty] => {
+            let message = type_ident(ty).ok_or_else(|| {
+                Error::new_spanned(ty, "the message type must be a single identifier")
+            })?;
+
+            let expected = naming.handler(message);
+            if ident != &expected {
+                return Err(Error::new(
+                    ident.span(),
+                    format!(
+                        "handler for `{}` must be named `{}`",
+                        message, expected
+                    ),
+                ));
+            }
+
+            quote!(#message)
+        }
+        _ => {
+            return Err(Error::new(
+                ident.span(),
+                format!("handler `{}` must take the message as its only argument", ident),
+            ))
+        }
+    };
+
+    let ret = match &method.sig.decl.output {
+        ReturnType::Type(_, ty) => type_ident(ty).ok_or_else(|| {
+            Error::new_spanned(ty, "the returned type must be a single identifier")
+        })?,
+        ReturnType::Default => {
+            return Err(Error::new(
+                ident.span(),
+                format!("handler `{}` must return the next state", ident),
+            ))
+        }
+    };
+
+    let targets = take_targets(&mut method.attrs)?;
+
+    let tokens = match targets {
+        // returns the machine enum, the end states are only known from the attribute
+        Some(states) if ret == machine_name => quote! {
+            (#state, #trigger) => [#(#states),*]
+        },
+        Some(states) => {
+            let outcome = outcome_path(mod_name, ret);
+            quote! {
+                (#state, #trigger) => [#(#states),*] as #outcome
+            }
+        }
+        None if ret == machine_name => {
+            return Err(Error::new(
+                ret.span(),
+                format!(
+                    "handler returning `{}` must list its end states with `#[targets(...)]`",
+                    machine_name
+                ),
+            ))
+        }
+        None => match enums.iter().find(|(name, _)| name == ret) {
+            Some((_, states)) => {
+                let outcome = outcome_path(mod_name, ret);
+                quote! {
+                    (#state, #trigger) => [#(#states),*] as #outcome
+                }
+            }
+            None => quote! {
+                (#state, #trigger) => #ret
+            },
+        },
+    };
+
+    Ok(tokens)
+}
+
+// the only parameter passed by reference is the machine's context, as the
+// handlers of `#[handlers]` cannot post messages to a runner's queue
+fn check_context(machine: &Machine, reference: &TypeReference) -> Result<()> {
+    let elem = &reference.elem;
+    let elem = quote!(#elem).to_string();
+
+    if let Some(context) = machine.context() {
+        if quote!(#context).to_string() == elem {
+            return match reference.mutability {
+                Some(_) => Ok(()),
+                None => Err(Error::new_spanned(
+                    reference,
+                    format!("the context must be taken as `&mut {}`", elem),
+                )),
+            };
+        }
+    }
+
+    let queue = format!("{}Queue", machine.name());
+    let message = if elem == queue {
+        format!("the handlers of `#[handlers]` cannot take `&mut {}`, as no runner is generated", queue)
+    } else {
+        match machine.context() {
+            Some(context) => format!(
+                "`{}` is not the context of `{}`, which is `{}`",
+                elem,
+                machine.name(),
+                quote!(#context)
+            ),
+            None => format!("`{}` has no context, the message must be passed by value", machine.name()),
+        }
+    };
+
+    Err(Error::new_spanned(reference, message))
+}
+
+/// removes the `#[targets(...)]` helper attribute and returns its states
+fn take_targets(attrs: &mut Vec<Attribute>) -> Result<Option<Vec<Ident>>> {
+    let position = attrs.iter().position(|a| a.path.is_ident("targets"));
+
+    match position {
+        Some(i) => {
+            let attr = attrs.remove(i);
+            let targets: Targets = syn::parse2(attr.tts)?;
+            Ok(Some(targets.states))
+        }
+        None => Ok(None),
+    }
+}
+
+fn outcome_path(mod_name: Option<&Ident>, outcome: &Ident) -> Path {
+    match mod_name {
+        Some(mod_name) => parse_quote!(#mod_name::#outcome),
+        None => parse_quote!(#outcome),
+    }
+}
+
+fn type_ident(ty: &Type) -> Option<&Ident> {
+    match ty {
+        Type::Path(p) if p.qself.is_none() && p.path.segments.len() == 1 => {
+            Some(&p.path.segments[0].ident)
+        }
+        _ => None,
+    }
+}
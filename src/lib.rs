@@ -19,7 +19,7 @@
 //!
 //! ```toml
 //! [dependencies]
-//! machine = "^0.2"
+//! machine = "^0.3"
 //! ```
 //!
 //! Then include it in your code like this:
@@ -38,7 +38,7 @@
 //!
 //! ```rust,ignore
 //! machine!(
-//!   enum Traffic {
+//!   pub enum Traffic {
 //!     Green { count: u8 },
 //!     Orange,
 //!     Red
@@ -389,117 +389,1408 @@
 //!   }
 //! }
 //! ```
-
-extern crate case;
-extern crate proc_macro;
-
-#[macro_use]
-extern crate log;
-#[macro_use]
-extern crate syn;
-#[macro_use]
-extern crate quote;
-
-mod machine;
-mod methods;
-mod transitions;
-
-use std::fs::{self, File, OpenOptions};
-use std::io::{Seek, Write};
-
-use machine::Machine;
-use methods::Methods;
-use transitions::Transitions;
-
-static OUTPUT_DIR: &'static str = "target/machine";
-
-#[proc_macro]
-pub fn machine(input: proc_macro::TokenStream) -> syn::export::TokenStream {
-    let machine = parse_macro_input!(input as Machine);
-    trace!("parsed machine: {:#?}", machine);
-
-    let (name, stream) = machine.generate();
-    trace!("generated: {}", stream);
-
-    let output_dir = format!("{}/{}", OUTPUT_DIR, name.to_string().to_lowercase());
-
-    fs::create_dir_all(&output_dir).expect("error creating directory");
-
-    let file_name = format!("{}/machine.rs", output_dir);
-
-    OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(&file_name)
-        .and_then(|mut file| {
-            file.write_all(stream.to_string().as_bytes())?;
-            file.flush()?;
-
-            trace!("wrote machine: {:?}", file_name);
-            Ok(())
-        })
-        .expect("error writing machine definition");
-
-    stream
-}
-
-#[proc_macro]
-pub fn transitions(input: proc_macro::TokenStream) -> syn::export::TokenStream {
-    let transitions = parse_macro_input!(input as Transitions);
-    trace!("parsed transitions: {:#?}", transitions);
-
-    let (name, stream) = transitions.generate();
-    trace!("generated transitions: {}", stream);
-
-    let file_name = format!("target/{}.rs", name.to_string().to_lowercase());
-    File::create(&file_name)
-        .and_then(|mut file| {
-            file.seek(std::io::SeekFrom::End(0))?;
-            file.write_all(stream.to_string().as_bytes())?;
-            file.flush()?;
-
-            trace!("wrote transitions: {:?}", file_name);
-            Ok(())
-        })
-        .expect("error writing machine definition");
-
-    let rendered = transitions.render_dot();
-
-    let file_name = format!("target/{}.dot", name.to_string().to_lowercase());
-    File::create(&file_name)
-        .and_then(|mut file| {
-            file.seek(std::io::SeekFrom::End(0))?;
-            file.write_all(rendered.as_bytes())?;
-            file.flush()?;
-
-            trace!("wrote dot file: {:?}", file_name);
-            Ok(())
-        })
-        .expect("error writing dot file");
-
-    stream
-}
-
-#[proc_macro]
-pub fn methods(input: proc_macro::TokenStream) -> syn::export::TokenStream {
-    let methods = parse_macro_input!(input as Methods);
-    trace!("\nparsed methods: {:#?}", methods);
-
-    let (name, stream) = methods.generate();
-    trace!("generated methods: {}", stream);
-
-    let file_name = format!("target/{}.rs", name.to_string().to_lowercase());
-    File::create(&file_name)
-        .and_then(|mut file| {
-            file.seek(std::io::SeekFrom::End(0))?;
-            file.write_all(stream.to_string().as_bytes())?;
-            file.flush()?;
-
-            trace!("wrote methods: {:?}", file_name);
-            Ok(())
-        })
-        .expect("error writing machine definition");
-
-    stream
+//!
+//! ### Validation
+//!
+//! `machine!` defines a hidden macro describing the states and their
+//! members. `transitions!` and `methods!` go through it to check that every
+//! state they mention exists and that accessors refer to existing members,
+//! so the machine must be defined before them (in the same module, or in a
+//! module marked with `#[macro_use]`):
+//!
+//! ```text,ignore
+//! error: state `Yellow` is not declared in machine `Traffic`
+//!   --> src/main.rs:19:6
+//!    |
+//! 19 |     (Yellow, Advance) => Red,
+//!    |      ^^^^^^
+//!
+//! error: state `Orange` has no field `count` in machine `Traffic`
+//!   --> src/main.rs:25:13
+//!    |
+//! 25 |     [Green, Orange] => get count: u8,
+//!    |             ^^^^^^
+//! ```
+//!
+//! A table or a list of methods written where the machine's description is not
+//! in scope, such as a sibling module without `#[macro_use]`, can skip these
+//! checks with the `unchecked` option. History targets, delegation, `..carry`
+//! and the `typed` option read the description, so they are not available there:
+//!
+//! ```rust,ignore
+//! transitions!(Door, unchecked, [(Open, Push) => Closed]);
+//! methods!(Door, unchecked, [Closed => get locked: bool]);
+//! ```
+//!
+//! The `transitions` macro also rejects tables where the same state and message
+//! combination appears more than once, whether the entries are identical or
+//! lead to different states:
+//!
+//! ```rust,compile_fail
+//! # #[macro_use] extern crate machine;
+//! machine!(
+//!   enum Traffic {
+//!     Green { count: u8 },
+//!     Orange,
+//!     Red
+//!   }
+//! );
+//!
+//! #[derive(Clone,Debug,PartialEq)]
+//! pub struct Advance;
+//!
+//! transitions!(Traffic,
+//!   [
+//!     (Green, Advance) => Orange,
+//!     (Green, Advance) => Red
+//!   ]
+//! );
+//!
+//! impl Green {
+//!   pub fn on_advance(self, _: Advance) -> Orange {
+//!     Orange {}
+//!   }
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! ```text,ignore
+//! error: conflicting transitions for `(Green, Advance)`: `Orange` and `Red`
+//!   --> src/main.rs:15:5
+//!    |
+//! 15 |     (Green, Advance) => Red
+//!    |     ^^^^^^^^^^^^^^^^
+//!
+//! error: `(Green, Advance)` was first declared here
+//!   --> src/main.rs:14:5
+//!    |
+//! 14 |     (Green, Advance) => Orange,
+//!    |     ^^^^^^^^^^^^^^^^
+//! ```
+//!
+//! ### Handler traits
+//!
+//! Passing the `traits` option to `transitions!` generates one trait per
+//! state, named after the machine and the state, listing the handlers that state
+//! must implement:
+//!
+//! ```rust,ignore
+//! transitions!(Traffic, traits,
+//!   [
+//!     (Green, Advance) => Orange,
+//!     (Green, PassCar) => [Green, Orange],
+//!     (Orange, Advance) => Red,
+//!     (Red, Advance) => Green
+//!   ]
+//! );
+//! ```
+//!
+//! ```rust,ignore
+//! pub trait TrafficGreenHandlers {
+//!   fn on_advance(self, input: Advance) -> Orange;
+//!   fn on_pass_car(self, input: PassCar) -> Traffic;
+//! }
+//!
+//! pub trait TrafficOrangeHandlers {
+//!   fn on_advance(self, input: Advance) -> Red;
+//! }
+//!
+//! pub trait TrafficRedHandlers {
+//!   fn on_advance(self, input: Advance) -> Green;
+//! }
+//! ```
+//!
+//! The wrapper methods then call the handlers through these traits, so each
+//! state needs an `impl TrafficGreenHandlers for Green` block, and the compiler
+//! reports exactly which handlers are missing, with their signatures:
+//!
+//! ```text,ignore
+//! error[E0046]: not all trait items implemented, missing: `on_pass_car`
+//!   --> src/main.rs:15:1
+//!    |
+//! 11 |     (Green, PassCar) => [Green, Orange],
+//!    |     ---------------- `on_pass_car` from trait
+//! ...
+//! 15 | impl TrafficGreenHandlers for Green {
+//!    | ^^^^^^^^^^^^^^^^^^^^^^^^^^^^ missing `on_pass_car` in implementation
+//! ```
+//!
+//! ### Inferring transitions from handlers
+//!
+//! Instead of writing the table in `transitions!`, the `handlers` attribute can
+//! read it from the `on_*` methods of the states. Each handler takes the message
+//! as its only argument, and its return type gives the end state:
+//!
+//! ```rust,ignore
+//! #[handlers(Traffic)]
+//! mod traffic_handlers {
+//!   use super::*;
+//!
+//!   pub enum PassCarOutcome {
+//!     Green(Green),
+//!     Orange(Orange),
+//!   }
+//!
+//!   impl Green {
+//!     pub fn on_advance(self, _: Advance) -> Orange {
+//!       Orange {}
+//!     }
+//!
+//!     pub fn on_pass_car(self, input: PassCar) -> PassCarOutcome {
+//!       let count = self.count + input.count;
+//!       if count >= 10 {
+//!         PassCarOutcome::Orange(Orange {})
+//!       } else {
+//!         PassCarOutcome::Green(Green { count })
+//!       }
+//!     }
+//!
+//!     #[targets(Green, Red)]
+//!     pub fn on_reset(self, _: Reset) -> Traffic {
+//!       Traffic::red()
+//!     }
+//!   }
+//!
+//!   impl Orange {
+//!     pub fn on_advance(self, _: Advance) -> Red {
+//!       Red {}
+//!     }
+//!   }
+//!
+//!   impl Red {
+//!     pub fn on_advance(self, _: Advance) -> Green {
+//!       Green { count: 0 }
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! A handler can return:
+//! - a state, for transitions with one end state
+//! - an outcome enum, with one variant wrapping each possible end state. It is
+//!   found automatically when declared in the same module, otherwise its variants
+//!   must be listed with `#[targets(...)]`
+//! - the machine enum, in which case the end states must be listed with `#[targets(...)]`
+//!
+//! The message is taken by value. With a context, the handlers take `&mut Ctx`
+//! before the message, and any other parameter passed by reference is an error.
+//! Since the wrappers are generated outside of the module, the handlers must be `pub`.
+//! This generates the same code and dot file as the equivalent `transitions!` table:
+//!
+//! ```rust,ignore
+//! transitions!(Traffic,
+//!   [
+//!     (Green, Advance) => Orange,
+//!     (Green, PassCar) => [Green, Orange] as traffic_handlers::PassCarOutcome,
+//!     (Green, Reset) => [Green, Red],
+//!     (Orange, Advance) => Red,
+//!     (Red, Advance) => Green
+//!   ]
+//! );
+//! ```
+//!
+//! The attribute can also be applied directly to an `impl` block, but since each
+//! invocation generates its own messages enum, all the handlers of a machine
+//! must then be in that block: a second annotated block for the same machine is
+//! rejected.
+//!
+//! The macros call each other through the `machine` crate's path, so the
+//! attribute also works as `#[machine::handlers(Traffic)]` without
+//! `#[macro_use]`, as long as the crate is not renamed.
+//!
+//! ### Handler bodies
+//!
+//! Trivial handlers can be written directly in the table, after the end states:
+//!
+//! ```rust,ignore
+//! transitions!(Traffic,
+//!   [
+//!     (Green, Advance) => Orange {},
+//!     (Orange, Advance) => Red { Red {} },
+//!     (Red, Advance) => Green |_state, _input| Green { count: 0 },
+//!     (Green, PassCar) => [Green, Orange] {
+//!       let count = self.count + input.count;
+//!       if count >= 10 {
+//!         Traffic::orange()
+//!       } else {
+//!         Traffic::green(count)
+//!       }
+//!     }
+//!   ]
+//! );
+//! ```
+//!
+//! The body can be:
+//! - `{}` to build an end state that has no members
+//! - a block, in which `self` is the current state and `input` the message
+//! - a closure taking the current state and the message
+//!
+//! The corresponding `on_*` method is then generated on the state, or in its
+//! handlers trait with the `traits` option.
+//!
+//! A transition to a state without members needs no body at all: when no handler
+//! is written for it, the one building the end state is used. A handler written
+//! on the state, or in its handlers trait, takes precedence.
+//!
+//! ### Carrying members over
+//!
+//! When the end state shares members with the start state, the handler body can
+//! list only the members that change, and `..carry` copies the other ones from
+//! the start state:
+//!
+//! ```rust,ignore
+//! machine!(
+//!   enum State {
+//!     Start { x: u8, name: String },
+//!     End { x: u8, name: String, y: bool },
+//!   }
+//! );
+//!
+//! transitions!(State,
+//!   [
+//!     (Start, Msg1) => End { y: true, ..carry },
+//!     (End, Msg1) => End { y: !self.y, ..carry }
+//!   ]
+//! );
+//! ```
+//!
+//! This generates:
+//!
+//! ```rust,ignore
+//! impl Start {
+//!   pub fn on_msg1(self, input: Msg1) -> End {
+//!     End { y: true, x: self.x, name: self.name }
+//!   }
+//! }
+//! ```
+//!
+//! Only members with the same name and type in both states are carried over.
+//! Listing a member that the end state does not have, or leaving out one that
+//! cannot be carried over, is a compile error. Members can also be given in the
+//! shorthand form, as in `Sending { input, ..carry }` to store the message.
+//!
+//! ### Timed transitions
+//!
+//! A transition can fire after the machine spent some time in a state, instead
+//! of on a message:
+//!
+//! ```rust,ignore
+//! use std::time::Duration;
+//!
+//! transitions!(Traffic,
+//!   [
+//!     (Green, Advance) => Orange,
+//!     (Orange, after(Duration::from_secs(5))) => Red,
+//!     (Red, after(Duration::from_secs(30))) => Green
+//!   ]
+//! );
+//!
+//! impl Orange {
+//!   pub fn on_timeout(self) -> Red {
+//!     Red {}
+//!   }
+//! }
+//! ```
+//!
+//! The handler for a timed transition is `on_timeout`, and takes no message.
+//! The following methods are generated on the machine:
+//! - `next_deadline(&self, entered_at: Instant) -> Option<Instant>`: when the
+//!   timed transition of the current state will fire, given the time the machine
+//!   entered it
+//! - `on_tick(self, entered_at: Instant, now: Instant) -> Traffic`: fires the
+//!   timed transition if its deadline has passed
+//!
+//! Since these handlers are taken, a machine with timed transitions cannot have
+//! messages whose handlers would be `on_timeout` or `on_tick`.
+//!
+//! Along with a `TrafficClock` trait, implemented by `TrafficSystemClock` for
+//! real time and by `TrafficManualClock`, which only moves forward when told to,
+//! and a `TrafficTimer` which keeps track of when the current state was entered:
+//!
+//! ```rust,ignore
+//! let clock = TrafficManualClock::new();
+//! let mut timer = TrafficTimer::new(Traffic::green(0), &clock);
+//!
+//! timer.update(|t| t.on_advance(Advance));
+//! assert_eq!(timer.state(), &Traffic::orange());
+//!
+//! clock.advance(Duration::from_secs(5));
+//! timer.tick();
+//! assert_eq!(timer.state(), &Traffic::red());
+//! ```
+//!
+//! ### Running a message queue
+//!
+//! With the `runner` option, handlers also get a `&mut TrafficQueue` to post
+//! messages of their own, and a message can be deferred in some states with
+//! `=> defer`:
+//!
+//! ```rust,ignore
+//! transitions!(Traffic, runner,
+//!   [
+//!     (Green, Advance) => Orange,
+//!     (Green, PassCar) => Green,
+//!     (Orange, Advance) => Red,
+//!     (Orange, PassCar) => defer,
+//!     (Red, Advance) => Green,
+//!     (Red, PassCar) => defer
+//!   ]
+//! );
+//!
+//! impl Orange {
+//!   pub fn on_advance(self, _: Advance, queue: &mut TrafficQueue) -> Red {
+//!     queue.post(Advance);
+//!     Red {}
+//!   }
+//! }
+//! ```
+//!
+//! A `TrafficRunner` owns the machine and a queue of `TrafficMessages`.
+//! `run_until_idle` dispatches the queued messages in order, including the ones
+//! posted by the handlers. A deferred message is kept aside until the machine
+//! changes state, then it is dispatched again before the rest of the queue:
+//!
+//! ```rust,ignore
+//! let mut runner = TrafficRunner::new(Traffic::orange());
+//! runner.post(PassCar { count: 1 });
+//! runner.post(Advance);
+//! runner.run_until_idle();
+//!
+//! assert_eq!(runner.state(), &Traffic::green(1));
+//! ```
+//!
+//! The runner also has an `on_*` method for each message, which handles it right
+//! away and leaves the messages posted by the handlers in the queue for
+//! `run_until_idle`.
+//!
+//! The machine itself has no `on_*` methods, as it could not keep the messages
+//! posted by the handlers. It gets `dispatch(self, TrafficMessages, &mut
+//! TrafficQueue)` instead, which keeps a deferred message in the queue until the
+//! state changes, and `defers(&self, &TrafficMessages) -> bool`.
+//!
+//! ### Completion transitions
+//!
+//! A state that should be left as soon as it is entered has a completion
+//! transition, written with `_` instead of a message:
+//!
+//! ```rust,ignore
+//! transitions!(Form,
+//!   [
+//!     (Editing, Submit) => Validating,
+//!     (Validating, _) => [Valid, Invalid]
+//!   ]
+//! );
+//!
+//! impl Validating {
+//!   pub fn on_complete(self) -> Form {
+//!     if self.text.is_empty() {
+//!       Form::invalid()
+//!     } else {
+//!       Form::valid(self.text)
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! After each message, the generated methods keep firing completion transitions
+//! until the machine reaches a state without one. The machine also gets a
+//! `complete(self) -> Form` method, to settle a state built with a constructor.
+//!
+//! If the machine is still moving after 32 completion transitions in a row, it
+//! goes into the `Error` state. The limit is set with the `max_steps` option:
+//!
+//! ```rust,ignore
+//! transitions!(Loop, max_steps = 4,
+//!   [
+//!     (Idle, Start) => Ping,
+//!     (Ping, _) => Pong,
+//!     (Pong, _) => Ping
+//!   ]
+//! );
+//!
+//! assert_eq!(Loop::idle().on_start(Start), Loop::error());
+//! ```
+//!
+//! With `#[handlers]`, an `on_complete(self)` method declares the completion
+//! transition of its state.
+//!
+//! ### Transition outputs
+//!
+//! With the `output` option, each transition also produces a list of outputs,
+//! such as commands to run or bytes to send:
+//!
+//! ```rust,ignore
+//! #[derive(Clone,Debug,PartialEq)]
+//! pub enum Command {
+//!   Beep,
+//!   StopTraffic,
+//! }
+//!
+//! transitions!(Traffic, output = Command,
+//!   [
+//!     (Green, Advance) => Orange,
+//!     (Orange, Advance) => Red,
+//!     (Red, Advance) => Green
+//!   ]
+//! );
+//!
+//! impl Green {
+//!   pub fn on_advance(self, _: Advance) -> (Orange, Vec<Command>) {
+//!     (Orange {}, vec![Command::Beep])
+//!   }
+//! }
+//! ```
+//!
+//! The handlers return the next state along with the outputs, and so do the
+//! generated methods:
+//!
+//! ```rust,ignore
+//! let (t, output) = Traffic::green(0).on_advance(Advance);
+//! assert_eq!(t, Traffic::orange());
+//! assert_eq!(output, vec![Command::Beep]);
+//! ```
+//!
+//! A `{}` or struct body produces no output. The outputs of completion
+//! transitions are appended to the ones of the message. `TrafficTimer::tick` and
+//! `TrafficTimer::update` return the outputs, and `TrafficRunner` collects them
+//! until `take_outputs` is called.
+//!
+//! In the generated `.dot` file, the edges of the transitions running a handler
+//! are labeled `Advance / Command`, while the delegated and history ones keep
+//! their trigger only.
+//!
+//! ### State outputs
+//!
+//! Each state can have an output of its own, declared with an `#[output(...)]`
+//! attribute on its variant:
+//!
+//! ```rust,ignore
+//! machine!(
+//!   #[derive(Clone,Debug,PartialEq)]
+//!   #[output(error = Lamp::Off)]
+//!   enum Traffic {
+//!     #[output(Lamp::Green)]
+//!     Green { count: u8 },
+//!     #[output(Lamp::Orange)]
+//!     Orange,
+//!     #[output(Lamp::Red)]
+//!     Red,
+//!   }
+//! );
+//!
+//! assert_eq!(Traffic::orange().output(), Lamp::Orange);
+//! assert_eq!(Traffic::error().output(), Lamp::Off);
+//! ```
+//!
+//! This generates a `Traffic::output(&self) -> Lamp` method. Either all the
+//! states have an output, or none of them. The `#[output(...)]` attribute on the
+//! enum is optional. It can give the output type, if it cannot be inferred from
+//! the path of the first output, and the output of the `Error` state, which is
+//! `Default::default()` otherwise:
+//!
+//! ```rust,ignore
+//! machine!(
+//!   #[output(u8, error = 0)]
+//!   enum Dimmer {
+//!     #[output(0)]
+//!     Off,
+//!     #[output(255)]
+//!     On,
+//!   }
+//! );
+//! ```
+//!
+//! In the generated `.dot` file, the states are labeled `Green / Lamp::Green`.
+//!
+//! ### Effects
+//!
+//! To keep the handlers pure, the side effects of a transition can be returned
+//! instead of performed, with the `effects` option listing the effect types:
+//!
+//! ```rust,ignore
+//! #[derive(Clone,Debug,PartialEq)]
+//! pub struct StartTimer { seconds: u64 }
+//!
+//! #[derive(Clone,Debug,PartialEq)]
+//! pub struct Log { line: String }
+//!
+//! transitions!(Traffic, effects = [StartTimer, Log],
+//!   [
+//!     (Green, Advance) => Orange,
+//!     (Orange, Advance) => Red,
+//!     (Red, Advance) => Green
+//!   ]
+//! );
+//!
+//! impl Green {
+//!   pub fn on_advance(self, _: Advance) -> (Orange, Vec<TrafficEffects>) {
+//!     (Orange {}, vec![StartTimer { seconds: 5 }.into()])
+//!   }
+//! }
+//! ```
+//!
+//! This works like the `output` option, with a generated `TrafficEffects` enum
+//! as the output type. It has one variant per effect, and can be built from each
+//! effect with `into()`. It derives the same traits as the machine. Tests can
+//! then check the returned effects without performing them.
+//!
+//! The effects are performed by an implementation of the generated
+//! `TrafficEffectExecutor` trait, with one method per effect. Its `execute` and
+//! `execute_all` methods dispatch the effects to them:
+//!
+//! ```rust,ignore
+//! struct Executor;
+//!
+//! impl TrafficEffectExecutor for Executor {
+//!   fn start_timer(&mut self, effect: StartTimer) {
+//!     // ...
+//!   }
+//!
+//!   fn log(&mut self, effect: Log) {
+//!     println!("{}", effect.line);
+//!   }
+//! }
+//!
+//! let (t, effects) = Traffic::green(0).on_advance(Advance);
+//! Executor.execute_all(effects);
+//! ```
+//!
+//! ### Machine context
+//!
+//! Data shared by all the states, like configuration or counters, can be kept
+//! out of the states by declaring a context type before the enum:
+//!
+//! ```rust,ignore
+//! pub struct Ctx {
+//!   max_cars: u8,
+//!   cars: u32,
+//! }
+//!
+//! machine!(
+//!   context = Ctx,
+//!   #[derive(Clone,Debug,PartialEq)]
+//!   enum Traffic {
+//!     Green { count: u8 },
+//!     Orange,
+//!     Red,
+//!   }
+//! );
+//! ```
+//!
+//! Every handler then takes a `&mut Ctx` right after `self`, and the generated
+//! methods take it as well and pass it along:
+//!
+//! ```rust,ignore
+//! transitions!(Traffic,
+//!   [
+//!     (Green, PassCar) => [Green, Orange],
+//!     (Orange, Advance) => Red,
+//!     (Red, Advance) => Green |_, ctx, _| Green { count: 0 }
+//!   ]
+//! );
+//!
+//! impl Green {
+//!   pub fn on_pass_car(self, ctx: &mut Ctx, input: PassCar) -> Traffic {
+//!     ctx.cars += u32::from(input.count);
+//!     // ...
+//!   }
+//! }
+//!
+//! let mut ctx = Ctx { max_cars: 10, cars: 0 };
+//! let t = Traffic::green(0).on_pass_car(&mut ctx, PassCar { count: 1 });
+//! ```
+//!
+//! This applies to `complete`, `on_tick`, `TrafficTimer::tick`, `dispatch`
+//! and `TrafficRunner::run_until_idle` too. The methods generated by `methods!`
+//! for a `fn` also take the context after the receiver, and pass it to the
+//! states' methods.
+//!
+//! ### Superstates
+//!
+//! States can be grouped in a superstate with the `#[superstate(...)]` attribute.
+//! Superstates can be nested, by listing them from the outermost one:
+//!
+//! ```rust,ignore
+//! machine!(
+//!   #[derive(Clone,Debug,PartialEq)]
+//!   enum Connection {
+//!     Disconnected,
+//!     #[superstate(Online)]
+//!     Authenticating,
+//!     #[superstate(Online::Connected)]
+//!     Idle,
+//!     #[superstate(Online::Connected)]
+//!     Busy { job: u32 },
+//!     #[superstate(Online::Connected)]
+//!     Draining,
+//!   }
+//! );
+//! ```
+//!
+//! The `Connection` enum keeps one variant per state. Each superstate gets an
+//! enum of its direct children, like `pub enum Connected { Idle(Idle), Busy(Busy),
+//! Draining(Draining) }`, and a check on the machine, like
+//! `Connection::is_in_connected(&self) -> bool`.
+//!
+//! A transition from a superstate applies to all its states, unless a state, or
+//! a superstate closer to it, has its own transition for the same message. Its
+//! handler is implemented on the superstate's enum:
+//!
+//! ```rust,ignore
+//! transitions!(Connection,
+//!   [
+//!     (Disconnected, Connect) => Authenticating,
+//!     (Authenticating, Authenticated) => Idle,
+//!     (Idle, Start) => Busy,
+//!     (Online, Disconnect) => Disconnected,
+//!     (Draining, Disconnect) => Draining
+//!   ]
+//! );
+//!
+//! impl Online {
+//!   pub fn on_disconnect(self, _: Disconnect) -> Disconnected {
+//!     Disconnected {}
+//!   }
+//! }
+//!
+//! assert_eq!(Connection::busy(1).on_disconnect(Disconnect), Connection::disconnected());
+//! assert_eq!(Connection::draining().on_disconnect(Disconnect), Connection::draining());
+//! ```
+//!
+//! A transition must end in a state, not in a superstate. In the generated
+//! `.dot` file, the superstates are drawn as clusters around their states.
+//!
+//! Superstates are attributes rather than nested enums because a variant like
+//! `Connected { Idle, Busy }` would read as a struct variant with two fields, and
+//! the enum given to `#[machine_enum]` must stay valid Rust. The states all stay
+//! variants of the machine enum, so matching on it and the constructors do not
+//! depend on the hierarchy.
+//!
+//! ### History
+//!
+//! A transition can go back to the state a superstate was last in, instead of
+//! a given state:
+//!
+//! ```rust,ignore
+//! machine!(
+//!   #[derive(Clone,Debug,PartialEq)]
+//!   enum Player {
+//!     Stopped,
+//!     Paused,
+//!     #[superstate(Active)]
+//!     Loading,
+//!     #[superstate(Active::Running)]
+//!     Playing,
+//!     #[superstate(Active::Running)]
+//!     Seeking { position: u32 },
+//!   }
+//! );
+//!
+//! transitions!(Player,
+//!   [
+//!     (Stopped, Play) => Loading,
+//!     (Loading, Loaded) => Playing,
+//!     (Playing, Seek) => Seeking,
+//!     (Active, Pause) => Paused,
+//!     (Paused, Resume) => Active::history,
+//!     (Paused, ResumeExactly) => Active::deep_history
+//!   ]
+//! );
+//! ```
+//!
+//! `Active::deep_history` goes back to the last state of `Active`, with its
+//! members. `Active::history` goes back to the last direct child of `Active`: if
+//! it was `Running`, the machine goes into `Playing`, the first state of
+//! `Running`. If the machine was never in `Active`, both go into `Loading`, the
+//! first state of `Active`. Those first states must have no members.
+//!
+//! The last states are kept in a generated `PlayerHistory`. It is updated when
+//! the machine leaves `Active`, so the generated methods take it after the
+//! context only if their transitions can leave `Active` or go back to it:
+//!
+//! ```rust,ignore
+//! let mut history = PlayerHistory::new();
+//!
+//! let p = Player::playing().on_seek(Seek { position: 42 });
+//! let p = p.on_pause(&mut history, Pause);
+//! assert_eq!(p.on_resume_exactly(&mut history, ResumeExactly), Player::seeking(42));
+//! ```
+//!
+//! `PlayerTimer` and `PlayerRunner` keep their own history. Recording the
+//! history requires the states to implement `Clone`. The `typed` wrappers do not
+//! record it, so the `typed` option cannot be used with history targets.
+//!
+//! ### Parallel regions
+//!
+//! Independent machines can run side by side with `parallel!`, each region
+//! keeping its own state:
+//!
+//! ```rust,ignore
+//! transitions!(Playback,
+//!   [
+//!     (Stopped, Play) => Playing,
+//!     (Playing, Pause) => Paused,
+//!     (Playing, Stop) => Stopped
+//!   ]
+//! );
+//!
+//! transitions!(Volume,
+//!   [
+//!     (Normal, Mute) => Muted,
+//!     (Muted, Stop) => Normal
+//!   ]
+//! );
+//!
+//! parallel!(Player = Playback & Volume);
+//! ```
+//!
+//! This generates a `Player` structure with a `playback` and a `volume` field, a
+//! `PlayerMessages` enum with every message of the regions, and the `on_*` and
+//! `dispatch` methods. A message goes to each region whose current state has a
+//! transition for it, the other regions are left unchanged:
+//!
+//! ```rust,ignore
+//! let p = Player::new(Playback::playing(), Volume::muted());
+//! let p = p.dispatch(Stop.into());
+//! assert_eq!(p.playback, Playback::stopped());
+//! assert_eq!(p.volume, Volume::normal());
+//! ```
+//!
+//! The regions cannot use a context, a runner, outputs or history, and a message
+//! received by several regions must implement `Clone`. The graph written to
+//! `target/player.dot` has a cluster per region.
+//!
+//! ### Pushdown machines
+//!
+//! A transition to `push State` suspends the current state, and a `pop`
+//! transition goes back to it:
+//!
+//! ```rust,ignore
+//! transitions!(Traffic,
+//!   [
+//!     (Green, Advance) => Orange,
+//!     (Green, Open) => push Dialog,
+//!     (Dialog, Close) => pop
+//!   ]
+//! );
+//! ```
+//!
+//! The states are kept in a generated `TrafficStack`, whose `on_*` and
+//! `dispatch` methods send the message to the state at the top of the stack:
+//!
+//! ```rust,ignore
+//! let mut stack = TrafficStack::new(Traffic::green(0));
+//! stack.on_open(Open);
+//! assert_eq!(stack.top(), &Traffic::dialog());
+//! assert_eq!(stack.depth(), 2);
+//!
+//! stack.on_close(Close);
+//! assert_eq!(stack.top(), &Traffic::green(0));
+//! ```
+//!
+//! On the machine alone, a `push` transition enters the new state and a `pop`
+//! goes into `Error`, since there is no state to go back to. Popping the last
+//! state of the stack also goes into `Error`. Pushing requires the states to
+//! implement `Clone`, and `push` and `pop` cannot be used with a runner or
+//! outputs.
+//!
+//! ### Child machines
+//!
+//! A state holding another machine in one of its fields can pass messages to
+//! it, and react when it is done:
+//!
+//! ```rust,ignore
+//! machine!(
+//!   #[derive(Clone,Debug,PartialEq)]
+//!   enum Session {
+//!     Connecting { handshake: Handshake },
+//!     Established,
+//!     Failed,
+//!   }
+//! );
+//!
+//! transitions!(Session,
+//!   [
+//!     (Connecting, HandshakeMsg) => delegate handshake,
+//!     (Connecting, on_final(handshake)) => Established,
+//!     (Connecting, on_error(handshake)) => Failed
+//!   ]
+//! );
+//! ```
+//!
+//! `Session::on_handshake_msg` calls `on_handshake_msg` on the `Handshake` in
+//! `handshake`. If the child is then in `Error`, the `on_error(handshake)`
+//! transition fires, calling `Connecting::on_handshake_error(self)`. If it is in
+//! a final state, the `on_final(handshake)` transition fires, calling
+//! `Connecting::on_handshake_final(self)`. Otherwise, the session stays in
+//! `Connecting` with the updated child.
+//!
+//! The final states are the states without any transition, as told by the
+//! `is_final()` method that `transitions!` generates with the `final_states`
+//! option, so the child's table must use it:
+//!
+//! ```rust,ignore
+//! transitions!(Handshake, final_states,
+//!   [
+//!     (Hello, HandshakeMsg) => KeyExchange { rounds: 0 },
+//!     (KeyExchange, HandshakeMsg) => [KeyExchange, Done]
+//!   ]
+//! );
+//! ```
+//!
+//! The parent's table reads the child's description, so the child's
+//! `transitions!` call must come first. Its `on_*` methods must only take the
+//! message, and they are called with the child's own handler prefix.
+//!
+//! ### Typestate API
+//!
+//! With the `typed` option, `transitions!` also generates a wrapper whose type
+//! tells the current state, so that invalid sequences fail to compile:
+//!
+//! ```rust,ignore
+//! transitions!(Traffic, typed,
+//!   [
+//!     (Green, Advance) => Orange,
+//!     (Orange, Advance) => Red,
+//!     (Red, Advance) => Green
+//!   ]
+//! );
+//!
+//! let green = TrafficTyped::new(Green { count: 0 });
+//! let orange: TrafficTyped<Orange> = green.advance(Advance);
+//! // does not compile: `Orange` has no transition for `PassCar`
+//! // orange.pass_car(PassCar { count: 1 });
+//!
+//! let t: Traffic = orange.into();
+//! ```
+//!
+//! `TrafficTyped<Green>` has a method named after each message accepted by
+//! `Green`, calling the same handler as `Traffic::on_*`. Only the transitions
+//! with a single end state get such a method, and not those ending in a state
+//! with a completion transition, since the next state is not known at compile
+//! time. Any `TrafficTyped<S>` converts into `Traffic`. `TrafficTyped::new` only
+//! takes the state structs of `Traffic`, which implement the sealed
+//! `TrafficTypedState` trait.
+//!
+//! ### Attribute form
+//!
+//! `machine!` can also be written as an attribute on the enum, which keeps the
+//! definition visible to rustfmt and to the IDE:
+//!
+//! ```rust,ignore
+//! #[machine_enum]
+//! #[derive(Clone,Debug,PartialEq)]
+//! enum Traffic {
+//!   Green { count: u8 },
+//!   Orange,
+//!   Red,
+//! }
+//! ```
+//!
+//! The options of `machine!` go in the attribute, as in
+//! `#[machine_enum(context = Ctx)]`, and the generated code is the same. The
+//! attribute cannot be named `machine`, since the crate already exports the
+//! `machine!` macro under that name.
+//!
+//! ### Single definition
+//!
+//! `state_machine!` takes the states, the transitions and the methods of a
+//! machine at once:
+//!
+//! ```rust,ignore
+//! state_machine! {
+//!   #[derive(Clone,Debug,PartialEq)]
+//!   enum Traffic {
+//!     Green { count: u8 },
+//!     Orange,
+//!     Red,
+//!   }
+//!
+//!   transitions(typed) {
+//!     (Green, Advance) => Orange,
+//!     (Orange, Advance) => Red,
+//!     (Red, Advance) => Green { count: 0 }
+//!   }
+//!
+//!   methods {
+//!     Green => get count: u8,
+//!     [Green, Orange, Red] => fn can_pass(&self) -> bool
+//!   }
+//! }
+//! ```
+//!
+//! The options of `machine!` go before the enum, and those of `transitions!` in
+//! parentheses after `transitions`. Both blocks are optional. The generated code
+//! is the same as with the three macros, but it is checked as a whole, so that a
+//! method clashing with a transition handler is reported on its declaration. The
+//! code is written to `target/traffic.rs`, and `target/traffic.dot` shows the
+//! methods of each state next to it.
+//!
+//! ### Split transition tables
+//!
+//! The transitions of a machine can be split across several tables. The tables
+//! other than the main one are groups, each with its own messages enum:
+//!
+//! ```rust,ignore
+//! transitions!(Traffic, groups = [Pedestrian],
+//!   [
+//!     (Green, Advance) => Orange,
+//!     (Orange, Advance) => Red,
+//!     (Red, Advance) => Green
+//!   ]
+//! );
+//!
+//! mod pedestrian {
+//!   transitions!(Traffic, group = Pedestrian,
+//!     [
+//!       (Red, Walk) => Crossing,
+//!       (Crossing, Crossed) => Red
+//!     ]
+//!   );
+//! }
+//! ```
+//!
+//! The `Pedestrian` group generates the `on_walk` and `on_crossed` methods, a
+//! `TrafficPedestrianMessages` enum and a `Traffic::dispatch_pedestrian` method.
+//! The groups listed in the `groups` option of the main table are also variants
+//! of `TrafficMessages`, which gets a `dispatch` method (or the runner's
+//! `dispatch`) covering all the tables, and they are taken into account by
+//! `is_final()` when the main table uses the `final_states` option:
+//!
+//! ```rust,ignore
+//! let t = Traffic::red().dispatch(TrafficPedestrianMessages::from(Walk).into());
+//! assert_eq!(t, Traffic::crossing());
+//! ```
+//!
+//! A message can only appear in one table. A group table only has message
+//! transitions, without history, `push` or `pop`, and cannot use the `traits`,
+//! `runner`, `typed`, `final_states` or `effects` options. Its handlers do not
+//! take the queue of the runner.
+//!
+//! The graph in `target/traffic.dot` shows all the tables: each group table
+//! writes its own section with the edges of its transitions, and the main table
+//! draws the states and clusters and keeps the sections of the groups it lists.
+//!
+//! ### State module
+//!
+//! With `#[machine(mod = traffic)]`, the state structs and the superstate enums
+//! are generated in a `traffic` module instead of next to the machine enum, so
+//! that they do not clash with the messages or with the states of another
+//! machine:
+//!
+//! ```rust,ignore
+//! machine!(
+//!   #[machine(mod = traffic)]
+//!   #[derive(Clone,Debug,PartialEq)]
+//!   enum Traffic {
+//!     Idle,
+//!     Green { count: u8 },
+//!   }
+//! );
+//!
+//! // a message can now be named `Green`
+//! transitions!(Traffic, [(Idle, Green) => Green { count: 0 }]);
+//!
+//! impl traffic::Green {
+//!   pub fn on_pass_car(self, input: PassCar) -> Traffic {
+//!     Traffic::green(self.count + input.count)
+//!   }
+//! }
+//! ```
+//!
+//! The variants of the enum keep the names of the states, as in
+//! `Traffic::Green(traffic::Green { count: 0 })`. Private members of the states are `pub(super)`, to stay visible where the
+//! machine is declared. The messages enum is generated by `transitions!`, at the
+//! place of that call.
+//!
+//! ### Naming
+//!
+//! The names of the generated items can be changed with options of the
+//! `#[machine(...)]` attribute:
+//!
+//! ```rust,ignore
+//! machine!(
+//!   #[machine(
+//!     handler_prefix = "handle_",    // `on_` by default
+//!     messages = ClientInput,        // `ClientMessages` by default
+//!     error = Failed,                // `Error` by default
+//!     setter_suffix = "_ref",        // `_mut` by default
+//!     constructor_prefix = "new_"    // none by default
+//!   )]
+//!   #[derive(Clone,Debug,PartialEq)]
+//!   enum Client {
+//!     Idle,
+//!     AwaitingHTTPResponse { retries: u8 },
+//!   }
+//! );
+//! ```
+//!
+//! Here the handler of `HTTPRequest` is `handle_http_request`, the constructors
+//! are `Client::new_idle()`, `Client::new_awaiting_http_response(0)` and
+//! `Client::new_failed()`, and the setter of `retries` is `retries_ref()`. Every
+//! machine has an `is_error()` method, which the delegating transitions use to
+//! check their child machines. The `#[handlers]` attribute reads the methods
+//! starting with the machine's handler prefix, so `handle_complete` is the
+//! completion handler.
+//!
+//! Type names are converted to snake case by starting a new word at an uppercase
+//! letter that follows a lowercase letter or a digit, or at the last uppercase
+//! letter of a run followed by a lowercase one: `PassCar` gives `pass_car`,
+//! `HTTPRequest` gives `http_request` and `Utf8Decoder` gives `utf8_decoder`.
+//!
+//! ### Visibility
+//!
+//! The generated types take the visibility declared on the enum: with
+//! `pub(crate) enum Traffic`, the state structs, the superstate enums, the
+//! messages enum, and the runner, timer, history, stack and typed wrappers are all
+//! `pub(crate)`. Without a visibility, they are private to the module of the
+//! `machine!` call, so a table or methods written in another module need a
+//! machine visible there.
+//!
+//! The generated methods stay `pub`, which makes them as visible as their type. A
+//! state can set its own visibility, used for its struct and its constructor, and
+//! a method of `methods!` can be given one before `get`, `set` or `fn`:
+//!
+//! ```rust,ignore
+//! machine!(
+//!   #[derive(Clone,Debug,PartialEq)]
+//!   pub(crate) enum Traffic {
+//!     #[visibility(pub)]
+//!     Green { count: u8 },
+//!     Orange,
+//!   }
+//! );
+//!
+//! methods!(Traffic,
+//!   [
+//!     Green => pub(crate) get count: u8
+//!   ]
+//! );
+//! ```
+//!
+//! A `parallel!` machine is declared the same way, as in
+//! `parallel!(pub(crate) House = Light & Door)`.
+//!
+//! When upgrading from 0.2, which made every generated item `pub`, a machine
+//! written without a visibility now generates private items: write `pub enum`
+//! to keep them public.
+
+#![recursion_limit = "256"]
+
+extern crate proc_macro;
+
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate syn;
+#[macro_use]
+extern crate quote;
+
+mod handlers;
+mod machine;
+mod methods;
+mod naming;
+mod parallel;
+mod state_machine;
+mod transitions;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, Write};
+
+use syn::export::{Span, TokenStream2};
+use syn::Ident;
+
+use handlers::Handlers;
+use machine::Machine;
+use methods::Methods;
+use parallel::Parallel;
+use state_machine::StateMachine;
+use transitions::Transitions;
+
+static OUTPUT_DIR: &str = "target/machine";
+
+#[proc_macro]
+pub fn machine(input: proc_macro::TokenStream) -> syn::export::TokenStream {
+    let machine = parse_macro_input!(input as Machine);
+    expand_machine(machine)
+}
+
+/// `#[machine_enum]` on an enum, or `#[machine_enum(context = Ctx)]`: same as
+/// `machine!`, with the options written in the attribute
+#[proc_macro_attribute]
+pub fn machine_enum(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> syn::export::TokenStream {
+    let options = TokenStream2::from(attr);
+    let item = TokenStream2::from(item);
+    let input = if options.is_empty() {
+        item
+    } else {
+        quote!(#options, #item)
+    };
+
+    match syn::parse2::<Machine>(input) {
+        Ok(machine) => expand_machine(machine),
+        Err(error) => proc_macro::TokenStream::from(error.to_compile_error()),
+    }
+}
+
+fn expand_machine(machine: Machine) -> syn::export::TokenStream {
+    trace!("parsed machine: {:#?}", machine);
+
+    let (name, stream) = machine.generate();
+    trace!("generated: {}", stream);
+
+    let output_dir = format!("{}/{}", OUTPUT_DIR, name.to_string().to_lowercase());
+
+    fs::create_dir_all(&output_dir).expect("error creating directory");
+
+    let file_name = format!("{}/machine.rs", output_dir);
+
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&file_name)
+        .and_then(|mut file| {
+            file.write_all(stream.to_string().as_bytes())?;
+            file.flush()?;
+
+            trace!("wrote machine: {:?}", file_name);
+            Ok(())
+        })
+        .expect("error writing machine definition");
+
+    stream
+}
+
+#[proc_macro]
+pub fn transitions(input: proc_macro::TokenStream) -> syn::export::TokenStream {
+    let tokens = TokenStream2::from(input.clone());
+    let transitions = parse_macro_input!(input as Transitions);
+    trace!("parsed transitions: {:#?}", transitions);
+
+    if transitions.machine.is_none() && transitions.options.unchecked.is_none() {
+        let description = Machine::description_macro(&transitions.machine_name);
+        return describe(&description, "transitions", tokens);
+    }
+
+    if let Some(child) = transitions.undescribed_child() {
+        let description = Transitions::description_macro(&child);
+        return describe(&description, "transitions", tokens);
+    }
+
+    let errors = transitions.validate();
+    if !errors.is_empty() {
+        return compile_errors(&errors);
+    }
+
+    let (name, stream) = transitions.generate();
+    trace!("generated transitions: {}", stream);
+
+    let file_name = match &transitions.options.group {
+        Some(group) => format!(
+            "target/{}_{}.rs",
+            name.to_string().to_lowercase(),
+            group.to_string().to_lowercase()
+        ),
+        None => format!("target/{}.rs", name.to_string().to_lowercase()),
+    };
+    File::create(&file_name)
+        .and_then(|mut file| {
+            file.seek(std::io::SeekFrom::End(0))?;
+            file.write_all(stream.to_string().as_bytes())?;
+            file.flush()?;
+
+            trace!("wrote transitions: {:?}", file_name);
+            Ok(())
+        })
+        .expect("error writing machine definition");
+
+    let file_name = format!("target/{}.dot", name.to_string().to_lowercase());
+    let existing = fs::read_to_string(&file_name).ok();
+    let rendered = transitions.merge_dot(existing.as_deref());
+
+    File::create(&file_name)
+        .and_then(|mut file| {
+            file.seek(std::io::SeekFrom::End(0))?;
+            file.write_all(rendered.as_bytes())?;
+            file.flush()?;
+
+            trace!("wrote dot file: {:?}", file_name);
+            Ok(())
+        })
+        .expect("error writing dot file");
+
+    stream
+}
+
+#[proc_macro]
+pub fn methods(input: proc_macro::TokenStream) -> syn::export::TokenStream {
+    let tokens = TokenStream2::from(input.clone());
+    let methods = parse_macro_input!(input as Methods);
+    trace!("\nparsed methods: {:#?}", methods);
+
+    if methods.machine.is_none() && !methods.unchecked {
+        let description = Machine::description_macro(&methods.machine_name);
+        return describe(&description, "methods", tokens);
+    }
+
+    let errors = methods.validate();
+    if !errors.is_empty() {
+        return compile_errors(&errors);
+    }
+
+    let (name, stream) = methods.generate();
+    trace!("generated methods: {}", stream);
+
+    let file_name = format!("target/{}.rs", name.to_string().to_lowercase());
+    File::create(&file_name)
+        .and_then(|mut file| {
+            file.seek(std::io::SeekFrom::End(0))?;
+            file.write_all(stream.to_string().as_bytes())?;
+            file.flush()?;
+
+            trace!("wrote methods: {:?}", file_name);
+            Ok(())
+        })
+        .expect("error writing machine definition");
+
+    stream
+}
+
+#[proc_macro]
+pub fn state_machine(input: proc_macro::TokenStream) -> syn::export::TokenStream {
+    let state_machine = parse_macro_input!(input as StateMachine);
+    trace!("parsed state machine: {:#?}", state_machine);
+
+    let errors = state_machine.validate();
+    if !errors.is_empty() {
+        return compile_errors(&errors);
+    }
+
+    let stream = state_machine.generate();
+    trace!("generated state machine: {}", stream);
+
+    let name = state_machine.name().to_string().to_lowercase();
+
+    let file_name = format!("target/{}.rs", name);
+    File::create(&file_name)
+        .and_then(|mut file| {
+            file.write_all(stream.to_string().as_bytes())?;
+            file.flush()?;
+
+            trace!("wrote state machine: {:?}", file_name);
+            Ok(())
+        })
+        .expect("error writing machine definition");
+
+    let rendered = state_machine.render_dot();
+
+    let file_name = format!("target/{}.dot", name);
+    File::create(&file_name)
+        .and_then(|mut file| {
+            file.write_all(rendered.as_bytes())?;
+            file.flush()?;
+
+            trace!("wrote dot file: {:?}", file_name);
+            Ok(())
+        })
+        .expect("error writing dot file");
+
+    stream
+}
+
+#[proc_macro]
+pub fn parallel(input: proc_macro::TokenStream) -> syn::export::TokenStream {
+    let tokens = TokenStream2::from(input.clone());
+    let parallel = parse_macro_input!(input as Parallel);
+    trace!("parsed parallel: {:#?}", parallel);
+
+    if let Some(region) = parallel.undescribed() {
+        let description = Transitions::description_macro(region);
+        return describe(&description, "parallel", tokens);
+    }
+
+    let errors = parallel.validate();
+    if !errors.is_empty() {
+        return compile_errors(&errors);
+    }
+
+    let (name, stream) = parallel.generate();
+    trace!("generated parallel: {}", stream);
+
+    let file_name = format!("target/{}.rs", name.to_string().to_lowercase());
+    File::create(&file_name)
+        .and_then(|mut file| {
+            file.write_all(stream.to_string().as_bytes())?;
+            file.flush()?;
+
+            trace!("wrote parallel: {:?}", file_name);
+            Ok(())
+        })
+        .expect("error writing parallel definition");
+
+    let rendered = parallel.render_dot();
+
+    let file_name = format!("target/{}.dot", name.to_string().to_lowercase());
+    File::create(&file_name)
+        .and_then(|mut file| {
+            file.write_all(rendered.as_bytes())?;
+            file.flush()?;
+
+            trace!("wrote dot file: {:?}", file_name);
+            Ok(())
+        })
+        .expect("error writing dot file");
+
+    stream
+}
+
+#[proc_macro_attribute]
+pub fn handlers(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> syn::export::TokenStream {
+    let tokens = TokenStream2::from(item.clone());
+    let machine_name = parse_macro_input!(attr as Ident);
+    let mut item = parse_macro_input!(item as syn::Item);
+
+    // the handlers are inferred once the machine's description is known
+    Handlers::strip_targets(&mut item);
+    let description = Machine::description_macro(&machine_name);
+    let mut stream = proc_macro::TokenStream::from(quote!(#item));
+    stream.extend(describe(
+        &description,
+        "infer_handlers",
+        quote!(#machine_name, #tokens),
+    ));
+    stream
+}
+
+#[doc(hidden)]
+#[proc_macro]
+pub fn infer_handlers(input: proc_macro::TokenStream) -> syn::export::TokenStream {
+    let handlers = parse_macro_input!(input as Handlers);
+    trace!("\ninferred handlers: {:#?}", handlers);
+    handlers.generate()
+}
+
+/// calls the `description` macro generated by `machine!` or `transitions!`,
+/// which will invoke `callback` again with its definition prepended to `input`.
+/// The callback is named by its path, so that it is found without `#[macro_use]`
+fn describe(description: &Ident, callback: &str, input: TokenStream2) -> syn::export::TokenStream {
+    let callback = Ident::new(callback, Span::call_site());
+
+    let tokens = quote! {
+        #description! { ::machine::#callback! { #input } }
+    };
+
+    proc_macro::TokenStream::from(tokens)
+}
+
+fn compile_errors(errors: &[syn::Error]) -> syn::export::TokenStream {
+    let tokens = errors.iter().map(|e| e.to_compile_error());
+    proc_macro::TokenStream::from(quote!(#(#tokens)*))
 }
@@ -1,50 +1,520 @@
-use case::CaseExt;
-use syn::export::Span;
+use naming::{snake_case, Naming};
+use syn::export::{Span, TokenStream2};
 use syn::parse::{Parse, ParseStream, Result};
-use syn::{Attribute, Ident, ItemEnum};
+use syn::{Attribute, Error, Expr, Fields, Ident, ItemEnum, Path, Type, Visibility};
 
 #[derive(Debug)]
 pub struct Machine {
   attributes: Vec<Attribute>,
   data: ItemEnum,
+  outputs: Option<Outputs>,
+  /// `context = Ctx`: passed to every handler
+  context: Option<Type>,
+  /// the superstates of each state, outermost first
+  superstates: Vec<(Ident, Vec<Ident>)>,
+  /// `#[machine(mod = traffic)]`: the module holding the state structs
+  module: Option<Ident>,
+  /// the names of the generated items
+  naming: Naming,
+  /// `#[visibility(pub)]` on a state: the visibility of its struct and constructor
+  visibilities: Vec<(Ident, Visibility)>,
+}
+
+/// `#[visibility(pub(crate))]` on a state
+struct StateVisibility(Visibility);
+
+impl Parse for StateVisibility {
+  fn parse(input: ParseStream) -> Result<Self> {
+    let content;
+    parenthesized!(content in input);
+    Ok(StateVisibility(content.parse()?))
+  }
+}
+
+/// `#[machine(mod = traffic, error = Failed)]` on the enum
+#[derive(Default)]
+struct MachineOptions {
+  module: Option<Ident>,
+  naming: Naming,
+}
+
+impl Parse for MachineOptions {
+  fn parse(input: ParseStream) -> Result<Self> {
+    let content;
+    parenthesized!(content in input);
+
+    let mut options = MachineOptions::default();
+    while !content.is_empty() {
+      if content.peek(Token![mod]) {
+        let _: Token![mod] = content.parse()?;
+        let _: Token![=] = content.parse()?;
+        options.module = Some(content.parse()?);
+      } else {
+        let key: Ident = content.parse()?;
+        let _: Token![=] = content.parse()?;
+        if !options.naming.parse_option(&key, &content)? {
+          return Err(Error::new(key.span(), format!("unknown machine option `{}`", key)));
+        }
+      }
+
+      if !content.is_empty() {
+        let _: Token![,] = content.parse()?;
+      }
+    }
+
+    Ok(options)
+  }
+}
+
+/// per-state outputs, from the `#[output(...)]` attributes
+#[derive(Debug)]
+pub struct Outputs {
+  ty: Type,
+  error: Option<Expr>,
+  states: Vec<(Ident, Expr)>,
+}
+
+/// `#[output(Lamp::Green)]` on a state
+struct StateOutput(Expr);
+
+impl Parse for StateOutput {
+  fn parse(input: ParseStream) -> Result<Self> {
+    let content;
+    parenthesized!(content in input);
+    Ok(StateOutput(content.parse()?))
+  }
+}
+
+/// `#[output(Lamp, error = Lamp::Off)]` on the enum, both parts being optional
+struct OutputOptions {
+  ty: Option<Type>,
+  error: Option<Expr>,
+}
+
+impl Parse for OutputOptions {
+  fn parse(input: ParseStream) -> Result<Self> {
+    let content;
+    parenthesized!(content in input);
+
+    let mut options = OutputOptions { ty: None, error: None };
+
+    if !(content.peek(Ident) && content.peek2(Token![=])) {
+      options.ty = Some(content.parse()?);
+      if !content.is_empty() {
+        let _: Token![,] = content.parse()?;
+      }
+    }
+
+    if !content.is_empty() {
+      let key: Ident = content.parse()?;
+      if key != "error" {
+        return Err(Error::new(key.span(), format!("unknown output option `{}`", key)));
+      }
+      let _: Token![=] = content.parse()?;
+      options.error = Some(content.parse()?);
+    }
+
+    Ok(options)
+  }
 }
 
 impl Parse for Machine {
   fn parse(input: ParseStream) -> Result<Self> {
+    let context = if input.peek(Ident) && input.peek2(Token![=]) {
+      let option: Ident = input.parse()?;
+      if option != "context" {
+        return Err(Error::new(option.span(), format!("unknown machine option `{}`", option)));
+      }
+      let _: Token![=] = input.parse()?;
+      let context: Type = input.parse()?;
+      let _: Token![,] = input.parse()?;
+      Some(context)
+    } else {
+      None
+    };
+
     let attributes: Vec<Attribute> = input.call(Attribute::parse_outer)?;
     let data: syn::ItemEnum = input.parse()?;
+    let outputs = parse_outputs(&attributes, &data)?;
+    let superstates = parse_superstates(&data)?;
+
+    let options = match attributes.iter().find(|a| a.path.is_ident("machine")) {
+      Some(attr) => syn::parse2::<MachineOptions>(attr.tts.clone())?,
+      None => MachineOptions::default(),
+    };
+
+    let mut visibilities = Vec::new();
+    for variant in data.variants.iter() {
+      if let Some(attr) = variant.attrs.iter().find(|a| a.path.is_ident("visibility")) {
+        let vis: StateVisibility = syn::parse2(attr.tts.clone())?;
+        visibilities.push((variant.ident.clone(), vis.0));
+      }
+    }
+
+    let error = &options.naming.error;
+    if data.variants.iter().any(|v| &v.ident == error) {
+      return Err(Error::new(
+        error.span(),
+        format!("`{}` is the name of a state, choose another name for the error variant", error),
+      ));
+    }
+
+    Ok(Machine {
+      attributes,
+      data,
+      outputs,
+      context,
+      superstates,
+      module: options.module,
+      naming: options.naming,
+      visibilities,
+    })
+  }
+}
+
+/// `#[superstate(Online::Connected)]` on a state
+struct Superstates(Path);
 
-    Ok(Machine { attributes, data })
+impl Parse for Superstates {
+  fn parse(input: ParseStream) -> Result<Self> {
+    let content;
+    parenthesized!(content in input);
+    Ok(Superstates(content.call(Path::parse_mod_style)?))
+  }
+}
+
+fn parse_superstates(data: &ItemEnum) -> Result<Vec<(Ident, Vec<Ident>)>> {
+  let mut superstates: Vec<(Ident, Vec<Ident>)> = Vec::new();
+
+  for variant in data.variants.iter() {
+    let chain = match variant.attrs.iter().find(|a| a.path.is_ident("superstate")) {
+      Some(attr) => {
+        let path: Superstates = syn::parse2(attr.tts.clone())?;
+        path.0.segments.into_iter().map(|s| s.ident).collect::<Vec<_>>()
+      }
+      None => Vec::new(),
+    };
+
+    for (i, superstate) in chain.iter().enumerate() {
+      if superstate == &data.ident || data.variants.iter().any(|v| &v.ident == superstate) {
+        return Err(Error::new(
+          superstate.span(),
+          format!("superstate `{}` has the name of a state or of the machine", superstate),
+        ));
+      }
+
+      // a superstate is always nested in the same superstates
+      let known = superstates
+        .iter()
+        .filter_map(|(_, c)| c.iter().position(|s| s == superstate).map(|j| &c[..j]))
+        .next();
+      if let Some(parents) = known {
+        if parents != &chain[..i] {
+          return Err(Error::new(
+            superstate.span(),
+            format!("superstate `{}` is nested in different superstates", superstate),
+          ));
+        }
+      }
+    }
+
+    superstates.push((variant.ident.clone(), chain));
   }
+
+  Ok(superstates)
+}
+
+fn parse_outputs(attributes: &[Attribute], data: &ItemEnum) -> Result<Option<Outputs>> {
+  let mut states = Vec::new();
+  for variant in data.variants.iter() {
+    if let Some(attr) = variant.attrs.iter().find(|a| a.path.is_ident("output")) {
+      let output: StateOutput = syn::parse2(attr.tts.clone())?;
+      states.push((variant.ident.clone(), output.0));
+    }
+  }
+
+  let options = match attributes.iter().find(|a| a.path.is_ident("output")) {
+    Some(attr) => Some(syn::parse2::<OutputOptions>(attr.tts.clone())?),
+    None => None,
+  };
+
+  if states.is_empty() {
+    return match options {
+      Some(_) => Err(Error::new(
+        data.ident.span(),
+        "the states of the machine have no `#[output(...)]`",
+      )),
+      None => Ok(None),
+    };
+  }
+
+  if let Some(variant) = data.variants.iter().find(|v| !states.iter().any(|(s, _)| s == &v.ident)) {
+    return Err(Error::new(
+      variant.ident.span(),
+      format!("state `{}` has no `#[output(...)]`", variant.ident),
+    ));
+  }
+
+  let (ty, error) = match options {
+    Some(options) => (options.ty, options.error),
+    None => (None, None),
+  };
+
+  // `Lamp::Green` gives the `Lamp` type
+  let ty = match ty {
+    Some(ty) => ty,
+    None => match &states[0].1 {
+      Expr::Path(p) if p.path.segments.len() > 1 => {
+        let mut path: Path = p.path.clone();
+        path.segments.pop();
+        let segments = path.segments.iter();
+        let leading = path.leading_colon;
+        parse_quote!(#leading #(#segments)::*)
+      }
+      expr => {
+        return Err(Error::new_spanned(
+          expr,
+          "cannot infer the output type, declare it with `#[output(Type)]` on the enum",
+        ))
+      }
+    },
+  };
+
+  Ok(Some(Outputs { ty, error, states }))
 }
 
 impl Machine {
+  /// name of the hidden macro through which `transitions!` and `methods!`
+  /// get the machine definition
+  pub fn description_macro(machine_name: &Ident) -> Ident {
+    Ident::new(&format!("__machine_{}", machine_name), machine_name.span())
+  }
+
+  pub fn name(&self) -> &Ident {
+    &self.data.ident
+  }
+
+  pub fn naming(&self) -> &Naming {
+    &self.naming
+  }
+
+  /// the variant of the invalid state
+  pub fn error(&self) -> &Ident {
+    &self.naming.error
+  }
+
+  /// the declared visibility of the enum, given to the generated types
+  pub fn vis(&self) -> &Visibility {
+    &self.data.vis
+  }
+
+  // the visibility set on a state, if any
+  fn state_vis(&self, state: &Ident) -> Option<&Visibility> {
+    self.visibilities.iter().find(|(s, _)| s == state).map(|(_, vis)| vis)
+  }
+
+  /// `vis` for an item of the state module, where `pub(super)` reaches the
+  /// module declaring the machine
+  pub fn module_vis(&self, vis: &Visibility) -> TokenStream2 {
+    if self.module.is_none() {
+      return quote!(#vis);
+    }
+
+    match vis {
+      Visibility::Inherited => quote!(pub(super)),
+      Visibility::Restricted(r) => {
+        let path = &r.path;
+        let first = path.segments.first().map(|s| s.value().ident.to_string());
+        match first.as_deref() {
+          Some("self") if path.segments.len() == 1 => quote!(pub(super)),
+          Some("self") | Some("super") => quote!(pub(in super::#path)),
+          _ => quote!(#vis),
+        }
+      }
+      _ => quote!(#vis),
+    }
+  }
+
+  pub fn is_superstate(&self, state: &Ident) -> bool {
+    self.superstates.iter().any(|(_, chain)| chain.contains(state))
+  }
+
+  /// the superstates, outermost first
+  pub fn superstates(&self) -> Vec<&Ident> {
+    let mut superstates = Vec::new();
+    for (_, chain) in self.superstates.iter() {
+      for superstate in chain.iter() {
+        if !superstates.contains(&superstate) {
+          superstates.push(superstate);
+        }
+      }
+    }
+    superstates
+  }
+
+  /// the superstates containing `state`, outermost first
+  pub fn ancestors(&self, state: &Ident) -> Vec<&Ident> {
+    for (leaf, chain) in self.superstates.iter() {
+      if leaf == state {
+        return chain.iter().collect();
+      }
+      if let Some(i) = chain.iter().position(|s| s == state) {
+        return chain[..i].iter().collect();
+      }
+    }
+    Vec::new()
+  }
+
+  /// the direct children of a superstate: states and nested superstates
+  pub fn children(&self, superstate: &Ident) -> Vec<&Ident> {
+    let mut children = Vec::new();
+    for (leaf, chain) in self.superstates.iter() {
+      if let Some(i) = chain.iter().position(|s| s == superstate) {
+        let child = chain.get(i + 1).unwrap_or(leaf);
+        if !children.contains(&child) {
+          children.push(child);
+        }
+      }
+    }
+    children
+  }
+
+  /// the states in `state`, or `state` itself if it is not a superstate
+  pub fn leaves<'a>(&'a self, state: &'a Ident) -> Vec<&'a Ident> {
+    if !self.is_superstate(state) {
+      return vec![state];
+    }
+    self
+      .superstates
+      .iter()
+      .filter(|(_, chain)| chain.contains(state))
+      .map(|(leaf, _)| leaf)
+      .collect()
+  }
+
+  /// `Online::Connected(Connected::Idle(value))`: the value of `superstate`
+  /// built from the value of one of its states
+  pub fn wrap(&self, leaf: &Ident, superstate: &Ident, value: TokenStream2) -> TokenStream2 {
+    let mut path = self.ancestors(leaf);
+    path.push(leaf);
+
+    let mut value = value;
+    let mut i = path.len() - 1;
+    while i > 0 && path[i] != superstate {
+      let parent = self.path(path[i - 1]);
+      let child = path[i];
+      value = quote!(#parent::#child(#value));
+      i -= 1;
+    }
+    value
+  }
+
+  /// `traffic::Green` if the machine has a module, `Green` otherwise: the
+  /// path to the struct of a state or the enum of a superstate
+  pub fn path(&self, state: &Ident) -> TokenStream2 {
+    match &self.module {
+      Some(module) => quote!(#module::#state),
+      None => quote!(#state),
+    }
+  }
+
+  pub fn context(&self) -> Option<&Type> {
+    self.context.as_ref()
+  }
+
+  pub fn has_state(&self, state: &Ident) -> bool {
+    self.fields(state).is_some()
+  }
+
+  /// the output of `state`, as written in its `#[output(...)]` attribute
+  pub fn output(&self, state: &Ident) -> Option<&Expr> {
+    self
+      .outputs
+      .as_ref()?
+      .states
+      .iter()
+      .find(|(s, _)| s == state)
+      .map(|(_, expr)| expr)
+  }
+
+  pub fn states(&self) -> impl Iterator<Item = &Ident> {
+    self.data.variants.iter().map(|v| &v.ident)
+  }
+
+  pub fn fields(&self, state: &Ident) -> Option<&Fields> {
+    self
+      .data
+      .variants
+      .iter()
+      .find(|v| &v.ident == state)
+      .map(|v| &v.fields)
+  }
+
   pub fn generate(&self) -> (&Ident, syn::export::TokenStream) {
     let machine_name = &self.data.ident;
     let mut stream = proc_macro::TokenStream::new();
 
     stream.extend(self.generate_enum());
-    stream.extend(self.generate_variants());
+    stream.extend(self.generate_types());
     stream.extend(self.generate_impl());
+    stream.extend(self.generate_outputs());
+    stream.extend(self.generate_superstates());
+    stream.extend(self.generate_description());
 
     (machine_name, stream)
   }
 
-  fn generate_enum(&self) -> syn::export::TokenStream {
+  // the generated macro calls back `transitions!` or `methods!` with the
+  // machine definition prepended to their input, so they can check and use it
+  fn generate_description(&self) -> syn::export::TokenStream {
     let Machine {
       attributes,
       data: ast,
+      context,
+      ..
     } = self;
 
+    let macro_name = Machine::description_macro(&ast.ident);
+    let macro_name = Ident::new(&macro_name.to_string(), Span::call_site());
+    let context = context.as_ref().map(|context| quote!(context = #context,));
+
+    let tokens = quote! {
+      #[allow(unused_macros)]
+      #[doc(hidden)]
+      macro_rules! #macro_name {
+        (:: $krate:ident :: $callback:ident ! { $($args:tt)* }) => {
+          :: $krate :: $callback! { { #context #(#attributes)* #ast } $($args)* }
+        };
+      }
+    };
+
+    proc_macro::TokenStream::from(tokens)
+  }
+
+  // the attributes copied on the generated enum and structs
+  pub fn attributes(&self) -> Vec<&Attribute> {
+    self
+      .attributes
+      .iter()
+      .filter(|a| !a.path.is_ident("output") && !a.path.is_ident("machine"))
+      .collect()
+  }
+
+  fn generate_enum(&self) -> syn::export::TokenStream {
+    let attributes = self.attributes();
+    let ast = &self.data;
+
     let machine_name = &ast.ident;
+    let vis = self.vis();
+    let error = self.error();
     let variants_names = &ast.variants.iter().map(|v| &v.ident).collect::<Vec<_>>();
-    let structs_names = variants_names.clone();
+    let structs_names = variants_names.iter().map(|v| self.path(v)).collect::<Vec<_>>();
 
     // define the state enum
     let tokens = quote! {
       #(#attributes)*
-      pub enum #machine_name {
-        Error,
+      #vis enum #machine_name {
+        #error,
         #(#variants_names(#structs_names)),*
       }
     };
@@ -52,23 +522,48 @@ impl Machine {
     proc_macro::TokenStream::from(tokens)
   }
 
-  fn generate_variants(&self) -> syn::export::TokenStream {
-    let Machine {
-      attributes,
-      data: ast,
-    } = self;
+  // the state structs and the superstate enums, in the machine's module if
+  // it has one
+  fn generate_types(&self) -> syn::export::TokenStream {
+    let mut tokens = self.generate_variants();
+    tokens.extend(self.generate_superstate_enums());
 
-    let mut stream = proc_macro::TokenStream::new();
+    let vis = self.vis();
+    let tokens = match &self.module {
+      Some(module) => quote! {
+        #vis mod #module {
+          #[allow(unused_imports)]
+          use super::*;
+
+          #tokens
+        }
+      },
+      None => tokens,
+    };
+
+    proc_macro::TokenStream::from(tokens)
+  }
+
+  fn generate_variants(&self) -> TokenStream2 {
+    let attributes = &self.attributes();
+    let ast = &self.data;
+
+    let mut stream = TokenStream2::new();
 
     // define structs for each state
-    for ref variant in ast.variants.iter() {
+    for variant in ast.variants.iter() {
       let name = &variant.ident;
+      let vis = self.module_vis(self.state_vis(name).unwrap_or_else(|| self.vis()));
 
       let fields = &variant
         .fields
         .iter()
         .map(|f| {
-          let vis = &f.vis;
+          // private members stay visible where the machine is declared
+          let vis = match (&f.vis, &self.module) {
+            (syn::Visibility::Inherited, Some(_)) => quote!(pub(super)),
+            (vis, _) => quote!(#vis),
+          };
           let ident = &f.ident;
           let ty = &f.ty;
 
@@ -80,17 +575,36 @@ impl Machine {
 
       let tokens = quote! {
         #(#attributes)*
-        pub struct #name {
+        #vis struct #name {
           #(#fields),*
         }
       };
 
-      stream.extend(proc_macro::TokenStream::from(tokens));
+      stream.extend(tokens);
     }
 
     stream
   }
 
+  // an enum of the direct children of each superstate
+  fn generate_superstate_enums(&self) -> TokenStream2 {
+    let attributes = &self.attributes();
+    let vis = &self.module_vis(self.vis());
+
+    let enums = self.superstates().into_iter().map(|superstate| {
+      let children = &self.children(superstate);
+      let types = children;
+      quote! {
+        #(#attributes)*
+        #vis enum #superstate {
+          #(#children(#types)),*
+        }
+      }
+    });
+
+    quote!(#(#enums)*)
+  }
+
   fn generate_impl(&self) -> syn::export::TokenStream {
     let ast = &self.data;
     let machine_name = &ast.ident;
@@ -99,7 +613,7 @@ impl Machine {
       .variants
       .iter()
       .map(|variant| {
-        let fn_name = Ident::new(&variant.ident.to_string().to_snake(), Span::call_site());
+        let fn_name = self.naming.constructor(&variant.ident);
         let struct_name = &variant.ident;
 
         let args = &variant
@@ -117,9 +631,15 @@ impl Machine {
 
         let arg_names = &variant.fields.iter().map(|f| &f.ident).collect::<Vec<_>>();
 
+        let struct_path = self.path(struct_name);
+        let vis = match self.state_vis(struct_name) {
+          Some(vis) => quote!(#vis),
+          None => quote!(pub),
+        };
+
         quote! {
-          pub fn #fn_name(#(#args),*) -> #machine_name {
-            #machine_name::#struct_name(#struct_name {
+          #vis fn #fn_name(#(#args),*) -> #machine_name {
+            #machine_name::#struct_name(#struct_path {
               #(#arg_names),*
             })
           }
@@ -127,16 +647,90 @@ impl Machine {
       })
       .collect::<Vec<_>>();
 
+    let error = self.error();
+    let error_fn = self.naming.constructor(error);
+
     let tokens = quote! {
       impl #machine_name {
         #(#methods)*
 
-        pub fn error() -> #machine_name {
-          #machine_name::Error
+        pub fn #error_fn() -> #machine_name {
+          #machine_name::#error
+        }
+
+        /// whether the machine is in the invalid state
+        pub fn is_error(&self) -> bool {
+          matches!(self, #machine_name::#error)
+        }
+      }
+    };
+
+    proc_macro::TokenStream::from(tokens)
+  }
+
+  fn generate_outputs(&self) -> syn::export::TokenStream {
+    let outputs = match &self.outputs {
+      Some(outputs) => outputs,
+      None => return syn::export::TokenStream::new(),
+    };
+
+    let machine_name = &self.data.ident;
+    let error_variant = self.error();
+    let ty = &outputs.ty;
+    let error = match &outputs.error {
+      Some(error) => quote!(#error),
+      None => quote!(Default::default()),
+    };
+    let arms = outputs.states.iter().map(|(state, expr)| {
+      quote! {
+        #machine_name::#state(_) => #expr,
+      }
+    });
+
+    let tokens = quote! {
+      impl #machine_name {
+        /// the output associated with the current state
+        pub fn output(&self) -> #ty {
+          match self {
+            #machine_name::#error_variant => #error,
+            #(#arms)*
+          }
         }
       }
     };
 
     proc_macro::TokenStream::from(tokens)
   }
+
+  // the `is_in_*` checks of the superstates
+  fn generate_superstates(&self) -> syn::export::TokenStream {
+    let machine_name = &self.data.ident;
+    let mut stream = proc_macro::TokenStream::new();
+
+    for superstate in self.superstates() {
+      let patterns = self
+        .leaves(superstate)
+        .into_iter()
+        .map(|leaf| quote!(#machine_name::#leaf(_)));
+      let fn_name = Ident::new(
+        &format!("is_in_{}", snake_case(&superstate.to_string())),
+        Span::call_site(),
+      );
+
+      let tokens = quote! {
+        impl #machine_name {
+          pub fn #fn_name(&self) -> bool {
+            match self {
+              #(#patterns)|* => true,
+              _ => false,
+            }
+          }
+        }
+      };
+
+      stream.extend(proc_macro::TokenStream::from(tokens));
+    }
+
+    stream
+  }
 }
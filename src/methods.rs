@@ -1,21 +1,29 @@
 use std::collections::HashMap;
 
+use machine::Machine;
+use naming::Naming;
+use transitions::{parse_description, undeclared_state};
 use syn::{
-    Abi, Expr, FnArg, FnDecl, Generics, Ident, MethodSig, ReturnType, Type, WhereClause,
-    export::Span,
+    Abi, Error, Expr, FnArg, FnDecl, Generics, Ident, MethodSig, ReturnType, Type, Visibility,
+    WhereClause,
     parse::{Parse, ParseStream, Result},
     punctuated::Punctuated,
 };
 
 #[derive(Debug)]
 pub struct Methods {
+    pub machine: Option<Machine>,
     pub machine_name: Ident,
+    /// `unchecked`: generate the methods without the machine's description
+    pub unchecked: bool,
     pub methods: Vec<Method>,
 }
 
 #[derive(Debug)]
 pub struct Method {
     pub states: Vec<Ident>,
+    /// `Green => pub(crate) get count: u8`, the methods being `pub` otherwise
+    pub vis: Visibility,
     pub method_type: MethodType,
     pub default: DefaultValue,
 }
@@ -36,10 +44,7 @@ pub enum DefaultValue {
 
 impl DefaultValue {
     pub fn is_default(&self) -> bool {
-        match self {
-            DefaultValue::None => false,
-            _ => true,
-        }
+        !matches!(self, DefaultValue::None)
     }
 }
 
@@ -58,9 +63,23 @@ impl Parse for ParenVal {
 
 impl Parse for Methods {
     fn parse(input: ParseStream) -> Result<Self> {
+        let machine = parse_description(input)?;
         let machine_name: Ident = input.parse()?;
         let _: Token![,] = input.parse()?;
 
+        let mut unchecked = false;
+        if !input.peek(syn::token::Bracket) {
+            let option: Ident = input.parse()?;
+            if option != "unchecked" {
+                return Err(Error::new(
+                    option.span(),
+                    format!("unknown methods option `{}`", option),
+                ));
+            }
+            let _: Token![,] = input.parse()?;
+            unchecked = true;
+        }
+
         let content;
         bracketed!(content in input);
 
@@ -69,7 +88,9 @@ impl Parse for Methods {
         let methods: Vec<_> = punctuated.into_iter().collect();
 
         Ok(Methods {
+            machine,
             machine_name,
+            unchecked,
             methods,
         })
     }
@@ -93,6 +114,7 @@ impl Parse for Method {
         };
 
         let _: Token![=>] = input.parse()?;
+        let vis: Visibility = input.parse()?;
         let default_token: Option<Token![default]> = input.parse()?;
         let default = if default_token.is_some() {
             match input.parse::<ParenVal>() {
@@ -111,9 +133,9 @@ impl Parse for Method {
                 let _: Token![:] = input.parse()?;
                 let ty: Type = input.parse()?;
 
-                if i.to_string() == "get" {
+                if i == "get" {
                     MethodType::Get(name, ty)
-                } else if i.to_string() == "set" {
+                } else if i == "set" {
                     MethodType::Set(name, ty)
                 } else {
                     return Err(syn::Error::new(i.span(), "expected `get` or `set`"));
@@ -123,6 +145,7 @@ impl Parse for Method {
 
         Ok(Method {
             states,
+            vis,
             method_type,
             default,
         })
@@ -130,7 +153,6 @@ impl Parse for Method {
 }
 
 fn parse_method_sig(input: ParseStream) -> Result<MethodSig> {
-    //let vis: Visibility = input.parse()?;
     let constness: Option<Token![const]> = input.parse()?;
     let unsafety: Option<Token![unsafe]> = input.parse()?;
     let asyncness: Option<Token![async]> = input.parse()?;
@@ -153,13 +175,13 @@ fn parse_method_sig(input: ParseStream) -> Result<MethodSig> {
         abi,
         ident,
         decl: FnDecl {
-            fn_token: fn_token,
-            paren_token: paren_token,
-            inputs: inputs,
-            output: output,
+            fn_token,
+            paren_token,
+            inputs,
+            output,
             variadic: None,
             generics: Generics {
-                where_clause: where_clause,
+                where_clause,
                 ..generics
             },
         },
@@ -167,6 +189,58 @@ fn parse_method_sig(input: ParseStream) -> Result<MethodSig> {
 }
 
 impl Methods {
+    /// checks that the states are declared in the machine, and that the
+    /// accessed fields exist in each state
+    pub fn validate(&self) -> Vec<Error> {
+        let mut errors = Vec::new();
+
+        let machine = match &self.machine {
+            Some(machine) => machine,
+            None => return errors,
+        };
+
+        for method in self.methods.iter() {
+            let field = match &method.method_type {
+                MethodType::Get(ident, _) | MethodType::Set(ident, _) => Some(ident),
+                MethodType::Fn(_) => None,
+            };
+
+            for state in method.states.iter() {
+                let fields = match machine.fields(state) {
+                    Some(fields) => fields,
+                    None => {
+                        errors.push(undeclared_state(state, machine.name()));
+                        continue;
+                    }
+                };
+
+                if let Some(field) = field {
+                    if !fields.iter().any(|f| f.ident.as_ref() == Some(field)) {
+                        errors.push(Error::new(
+                            state.span(),
+                            format!(
+                                "state `{}` has no field `{}` in machine `{}`",
+                                state,
+                                field,
+                                machine.name()
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+
+        errors
+    }
+
+    /// the names of the generated items, from the machine's options
+    fn naming(&self) -> Naming {
+        self.machine
+            .as_ref()
+            .map(|machine| machine.naming().clone())
+            .unwrap_or_default()
+    }
+
     pub fn generate(&self) -> (&Ident, syn::export::TokenStream) {
         let machine_name = &self.machine_name;
         let mut stream = proc_macro::TokenStream::new();
@@ -184,12 +258,12 @@ impl Methods {
         for method in self.methods.iter() {
             for state in method.states.iter() {
                 let entry = h.entry(state).or_insert(Vec::new());
-                entry.push(&method.method_type);
+                entry.push(method);
             }
         }
 
         for (state, methods) in h.iter() {
-            stream.extend(self.generate_state_impl(*state, methods));
+            stream.extend(self.generate_state_impl(state, methods));
         }
 
         stream
@@ -198,24 +272,29 @@ impl Methods {
     pub fn generate_state_impl(
         &self,
         state: &syn::Ident,
-        methods: &[&MethodType],
+        methods: &[&Method],
     ) -> syn::export::TokenStream {
         let method_tokens = methods
             .iter()
             .map(|method| {
-                match method {
+                // the wrappers on the enum must reach the state's methods
+                let vis = match (&method.vis, &self.machine) {
+                    (Visibility::Inherited, _) => quote!(pub),
+                    (vis, Some(machine)) => machine.module_vis(vis),
+                    (vis, None) => quote!(#vis),
+                };
+                match &method.method_type {
                     MethodType::Get(ident, ty) => {
                         quote! {
-                          pub fn #ident(&self) -> &#ty {
+                          #vis fn #ident(&self) -> &#ty {
                             &self.#ident
                           }
                         }
                     }
                     MethodType::Set(ident, ty) => {
-                        let mut_ident =
-                            Ident::new(&format!("{}_mut", &ident.to_string()), Span::call_site());
+                        let mut_ident = self.naming().setter(ident);
                         quote! {
-                          pub fn #mut_ident(&mut self) -> &mut #ty {
+                          #vis fn #mut_ident(&mut self) -> &mut #ty {
                             &mut self.#ident
                           }
                         }
@@ -228,8 +307,13 @@ impl Methods {
             })
             .collect::<Vec<_>>();
 
+        let path = match &self.machine {
+            Some(machine) => machine.path(state),
+            None => quote!(#state),
+        };
+
         let tokens = quote! {
-            impl #state {
+            impl #path {
                 #(#method_tokens)*
             }
         };
@@ -277,8 +361,9 @@ impl Methods {
             })
             .collect::<Vec<_>>();
 
+        let vis = method_vis(method);
         let tokens = quote! {
-            pub fn #ident(&self) -> Option<&#ty> {
+            #vis fn #ident(&self) -> Option<&#ty> {
                 match self {
                     #(#variants)*
                     _ => None,
@@ -297,7 +382,7 @@ impl Methods {
     ) -> syn::export::TokenStream2 {
         let machine_name = &self.machine_name;
 
-        let mut_ident = Ident::new(&format!("{}_mut", &ident.to_string()), Span::call_site());
+        let mut_ident = self.naming().setter(ident);
 
         let variants = method
             .states
@@ -309,8 +394,9 @@ impl Methods {
             })
             .collect::<Vec<_>>();
 
+        let vis = method_vis(method);
         let tokens = quote! {
-            pub fn #mut_ident(&mut self) -> Option<&mut #ty> {
+            #vis fn #mut_ident(&mut self) -> Option<&mut #ty> {
                 match self {
                     #(#variants)*
                     _ => None,
@@ -333,10 +419,7 @@ impl Methods {
             .decl
             .inputs
             .iter()
-            .filter(|arg| match arg {
-                FnArg::Captured(_) => true,
-                _ => false,
-            })
+            .filter(|arg| matches!(arg, FnArg::Captured(_)))
             .map(|arg| {
                 if let FnArg::Captured(a) = arg {
                     &a.pat
@@ -346,6 +429,29 @@ impl Methods {
             })
             .collect::<Vec<_>>();
 
+        // `self` is hygienic, so we must match on the receiver written by the user
+        let receiver = signature
+            .decl
+            .inputs
+            .iter()
+            .filter_map(|arg| match arg {
+                FnArg::SelfRef(a) => Some(&a.self_token),
+                FnArg::SelfValue(a) => Some(&a.self_token),
+                _ => None,
+            })
+            .next();
+
+        // the machine's context is passed along, right after the receiver
+        let context = self.machine.as_ref().and_then(|machine| machine.context());
+        let ctx = context.map(|_| quote!(ctx,));
+        let inputs = signature.decl.inputs.iter().map(|arg| match (arg, context) {
+            (FnArg::SelfRef(_), Some(context)) | (FnArg::SelfValue(_), Some(context)) => {
+                quote!(#arg, ctx: &mut #context)
+            }
+            _ => quote!(#arg),
+        });
+        let inputs = quote!(#(#inputs),*);
+
         let variants = method
             .states
             .iter()
@@ -353,17 +459,16 @@ impl Methods {
                 let a = args.clone();
                 if method.default.is_default() {
                     quote! {
-                        #machine_name::#state(ref v) => v.#ident( #(#a),* ),
+                        #machine_name::#state(ref v) => v.#ident( #ctx #(#a),* ),
                     }
                 } else {
                     quote! {
-                        #machine_name::#state(ref v) => Some(v.#ident( #(#a),* )),
+                        #machine_name::#state(ref v) => Some(v.#ident( #ctx #(#a),* )),
                     }
                 }
             })
             .collect::<Vec<_>>();
 
-        let inputs = &signature.decl.inputs;
         let output = match &signature.decl.output {
             ReturnType::Default => quote! {},
             ReturnType::Type(arrow, ty) => {
@@ -379,11 +484,12 @@ impl Methods {
             }
         };
 
+        let vis = method_vis(method);
         match method.default {
             DefaultValue::None => {
                 quote! {
-                    pub fn #ident(#inputs) #output {
-                        match self {
+                    #vis fn #ident(#inputs) #output {
+                        match #receiver {
                             #(#variants)*
                             _ => None,
                         }
@@ -392,8 +498,8 @@ impl Methods {
             }
             DefaultValue::Default => {
                 quote! {
-                    pub fn #ident(#inputs) #output {
-                        match self {
+                    #vis fn #ident(#inputs) #output {
+                        match #receiver {
                             #(#variants)*
                             _ => std::default::Default::default(),
                         }
@@ -402,8 +508,8 @@ impl Methods {
             }
             DefaultValue::Val(ref expr) => {
                 quote! {
-                    pub fn #ident(#inputs) #output {
-                        match self {
+                    #vis fn #ident(#inputs) #output {
+                        match #receiver {
                             #(#variants)*
                             _ => #expr,
                         }
@@ -413,3 +519,11 @@ impl Methods {
         }
     }
 }
+
+// the visibility written before the method, `pub` by default
+fn method_vis(method: &Method) -> syn::export::TokenStream2 {
+    match &method.vis {
+        Visibility::Inherited => quote!(pub),
+        vis => quote!(#vis),
+    }
+}
@@ -0,0 +1,130 @@
+use syn::{
+    Error, Ident, LitStr,
+    export::Span,
+    parse::{ParseStream, Result},
+};
+
+/// the names of the generated items, set by the `#[machine(...)]` options
+#[derive(Clone, Debug)]
+pub struct Naming {
+    /// `handler_prefix = "on_"`: the start of the handler names
+    pub handler_prefix: String,
+    /// `messages = Input`: the messages enum, `<Machine>Messages` by default
+    pub messages: Option<Ident>,
+    /// `error = Error`: the variant of the invalid state
+    pub error: Ident,
+    /// `setter_suffix = "_mut"`: the end of the setter names
+    pub setter_suffix: String,
+    /// `constructor_prefix = ""`: the start of the constructor names
+    pub constructor_prefix: String,
+}
+
+impl Default for Naming {
+    fn default() -> Naming {
+        Naming {
+            handler_prefix: "on_".to_string(),
+            messages: None,
+            error: Ident::new("Error", Span::call_site()),
+            setter_suffix: "_mut".to_string(),
+            constructor_prefix: String::new(),
+        }
+    }
+}
+
+impl Naming {
+    /// parses the value of a naming option, returning false for the other keys
+    pub fn parse_option(&mut self, key: &Ident, input: ParseStream) -> Result<bool> {
+        if key == "messages" {
+            self.messages = Some(input.parse()?);
+        } else if key == "error" {
+            self.error = input.parse()?;
+        } else if key == "handler_prefix" {
+            self.handler_prefix = parse_affix(input, false)?;
+        } else if key == "setter_suffix" {
+            let suffix = parse_affix(input, true)?;
+            if suffix.is_empty() {
+                return Err(Error::new(key.span(), "the setter suffix cannot be empty"));
+            }
+            self.setter_suffix = suffix;
+        } else if key == "constructor_prefix" {
+            self.constructor_prefix = parse_affix(input, false)?;
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// `on_pass_car` for `PassCar`
+    pub fn handler(&self, message: &Ident) -> Ident {
+        let name = format!("{}{}", self.handler_prefix, snake_case(&message.to_string()));
+        Ident::new(&name, message.span())
+    }
+
+    /// `on_timeout` for `timeout`
+    pub fn handler_suffix(&self, suffix: &str) -> Ident {
+        Ident::new(&format!("{}{}", self.handler_prefix, suffix), Span::call_site())
+    }
+
+    pub fn messages(&self, machine_name: &Ident) -> Ident {
+        match &self.messages {
+            Some(messages) => messages.clone(),
+            None => Ident::new(&format!("{}Messages", machine_name), Span::call_site()),
+        }
+    }
+
+    /// `count_mut` for `count`
+    pub fn setter(&self, field: &Ident) -> Ident {
+        Ident::new(&format!("{}{}", field, self.setter_suffix), Span::call_site())
+    }
+
+    /// `green` for `Green`
+    pub fn constructor(&self, state: &Ident) -> Ident {
+        Ident::new(
+            &format!("{}{}", self.constructor_prefix, snake_case(&state.to_string())),
+            Span::call_site(),
+        )
+    }
+}
+
+// a string that can start or end an identifier
+fn parse_affix(input: ParseStream, suffix: bool) -> Result<String> {
+    let lit: LitStr = input.parse()?;
+    let value = lit.value();
+
+    let valid = value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && (suffix || !value.starts_with(|c: char| c.is_ascii_digit()));
+    if !valid {
+        return Err(Error::new(
+            lit.span(),
+            format!("`{}` cannot be part of an identifier", value),
+        ));
+    }
+
+    Ok(value)
+}
+
+/// converts a camel case name to snake case, keeping acronyms together:
+/// a word starts at an uppercase letter following a lowercase letter or a
+/// digit, or at the last uppercase letter of a run followed by a lowercase
+/// one, so `HTTPRequest` gives `http_request` and `Utf8Decoder` gives
+/// `utf8_decoder`
+pub fn snake_case(name: &str) -> String {
+    let chars = name.chars().collect::<Vec<_>>();
+    let mut snake = String::new();
+
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let previous = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            let starts_word = previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next_lower);
+            if starts_word {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+
+    snake
+}
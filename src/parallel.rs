@@ -0,0 +1,325 @@
+use naming::{snake_case, Naming};
+use syn::{
+    Error, Ident, LitStr, Pat, Visibility,
+    export::Span,
+    parse::{Parse, ParseStream, Result},
+    punctuated::Punctuated,
+};
+
+#[derive(Debug)]
+pub struct Parallel {
+    /// regions described by their `transitions!`
+    pub described: Vec<Region>,
+    /// `pub(crate) House = Light & Door`
+    pub vis: Visibility,
+    pub name: Ident,
+    pub regions: Vec<Ident>,
+}
+
+#[derive(Debug)]
+pub struct Region {
+    pub name: Ident,
+    /// whether the `on_*` methods only take the message
+    pub plain: bool,
+    /// whether the table generates `is_final()`
+    pub final_states: bool,
+    /// the start of the names of the region's handlers
+    pub handler_prefix: LitStr,
+    pub messages: Vec<Ident>,
+    /// messages with the pattern of a state that has a transition for them
+    pub accepts: Vec<(Ident, Pat)>,
+    pub edges: Vec<(Ident, LitStr, Ident)>,
+}
+
+impl Parse for Parallel {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut described = Vec::new();
+        while input.peek(syn::token::Brace) {
+            let content;
+            braced!(content in input);
+            described.push(content.parse()?);
+        }
+
+        let vis: Visibility = input.parse()?;
+        let name: Ident = input.parse()?;
+        let _: Token![=] = input.parse()?;
+
+        let regions: Punctuated<Ident, Token![&]> = Punctuated::parse_separated_nonempty(input)?;
+
+        Ok(Parallel {
+            described,
+            vis,
+            name,
+            regions: regions.into_iter().collect(),
+        })
+    }
+}
+
+impl Parse for Region {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let name: Ident = input.parse()?;
+        let kind: Ident = input.parse()?;
+        let finals: Ident = input.parse()?;
+        let handler_prefix: LitStr = input.parse()?;
+
+        let content;
+        bracketed!(content in input);
+        let messages: Punctuated<Ident, Token![,]> = content.parse_terminated(Ident::parse)?;
+
+        let content;
+        bracketed!(content in input);
+        let accepts: Punctuated<(Ident, Pat), Token![,]> = content.parse_terminated(|input| {
+            let pair;
+            parenthesized!(pair in input);
+            let message: Ident = pair.parse()?;
+            let _: Token![,] = pair.parse()?;
+            let pattern: Pat = pair.parse()?;
+            Ok((message, pattern))
+        })?;
+
+        let content;
+        bracketed!(content in input);
+        let edges: Punctuated<(Ident, LitStr, Ident), Token![,]> =
+            content.parse_terminated(|input| {
+                let edge;
+                parenthesized!(edge in input);
+                let start: Ident = edge.parse()?;
+                let _: Token![,] = edge.parse()?;
+                let label: LitStr = edge.parse()?;
+                let _: Token![,] = edge.parse()?;
+                let end: Ident = edge.parse()?;
+                Ok((start, label, end))
+            })?;
+
+        Ok(Region {
+            name,
+            plain: kind == "plain",
+            final_states: finals == "final_states",
+            handler_prefix,
+            messages: messages.into_iter().collect(),
+            accepts: accepts.into_iter().collect(),
+            edges: edges.into_iter().collect(),
+        })
+    }
+}
+
+impl Parallel {
+    /// the first region whose transitions are not described yet
+    pub fn undescribed(&self) -> Option<&Ident> {
+        self.regions
+            .iter()
+            .find(|region| !self.described.iter().any(|r| &r.name == *region))
+    }
+
+    fn region(&self, name: &Ident) -> &Region {
+        self.described.iter().find(|r| &r.name == name).unwrap()
+    }
+
+    /// checks that each region appears once, and that its methods can be
+    /// called with the message only
+    pub fn validate(&self) -> Vec<Error> {
+        let mut errors = Vec::new();
+
+        for (i, region) in self.regions.iter().enumerate() {
+            if self.regions[..i].contains(region) {
+                errors.push(Error::new(
+                    region.span(),
+                    format!("region `{}` appears more than once", region),
+                ));
+            } else if !self.region(region).plain {
+                errors.push(Error::new(
+                    region.span(),
+                    format!(
+                        "region `{}` cannot use a context, a runner, outputs or history",
+                        region
+                    ),
+                ));
+            }
+        }
+
+        errors
+    }
+
+    /// all the messages, in region order
+    fn messages(&self) -> Vec<&Ident> {
+        let mut messages = Vec::new();
+        for region in self.regions.iter() {
+            for message in self.region(region).messages.iter() {
+                if !messages.contains(&message) {
+                    messages.push(message);
+                }
+            }
+        }
+        messages
+    }
+
+    pub fn render_dot(&self) -> String {
+        let mut string = format!("digraph {} {{\n", self.name);
+
+        for region in self.regions.iter() {
+            let region = self.region(region);
+
+            let mut states = Vec::new();
+            for (start, _, end) in region.edges.iter() {
+                for state in [start, end].iter() {
+                    if !states.contains(state) {
+                        states.push(*state);
+                    }
+                }
+            }
+
+            string.push_str(&format!("    subgraph cluster_{} {{\n", region.name));
+            string.push_str(&format!("        label = \"{}\";\n", region.name));
+            for state in states {
+                string.push_str(&format!(
+                    "        {}_{} [ label = \"{}\" ];\n",
+                    region.name, state, state
+                ));
+            }
+            string.push_str("    }\n");
+        }
+
+        for region in self.regions.iter() {
+            let region = self.region(region);
+            for (start, label, end) in region.edges.iter() {
+                string.push_str(&format!(
+                    "    {}_{} -> {}_{} [ label = \"{}\" ];\n",
+                    region.name,
+                    start,
+                    region.name,
+                    end,
+                    label.value().replace('"', "\\\"")
+                ));
+            }
+        }
+
+        string.push('}');
+
+        string
+    }
+
+    pub fn generate(&self) -> (&Ident, syn::export::TokenStream) {
+        let vis = &self.vis;
+        let name = &self.name;
+        let message_enum = Ident::new(&format!("{}Messages", name), Span::call_site());
+
+        let regions = &self.regions;
+        let fields = &regions.iter().map(region_field).collect::<Vec<_>>();
+        let messages = &self.messages();
+        let messages1 = messages;
+
+        let conversions = messages.iter().map(|message| {
+            quote! {
+                impl From<#message> for #message_enum {
+                    fn from(message: #message) -> #message_enum {
+                        #message_enum::#message(message)
+                    }
+                }
+            }
+        });
+
+        let methods = messages.iter().map(|message| self.generate_fn(message));
+
+        let dispatch = messages.iter().map(|message| {
+            let fn_ident = Naming::default().handler(message);
+            quote! {
+                #message_enum::#message(input) => self.#fn_ident(input),
+            }
+        });
+
+        let fields1 = fields;
+        let fields2 = fields;
+        let regions1 = regions;
+        let tokens = quote! {
+            /// regions running side by side, each receiving the messages it accepts
+            #vis struct #name {
+                #(pub #fields1: #regions1),*
+            }
+
+            #[derive(Clone,Debug,PartialEq)]
+            #vis enum #message_enum {
+                #(#messages(#messages1)),*
+            }
+
+            #(#conversions)*
+
+            impl #name {
+                pub fn new(#(#fields: #regions),*) -> #name {
+                    #name { #(#fields2),* }
+                }
+
+                #(#methods)*
+
+                pub fn dispatch(self, message: #message_enum) -> #name {
+                    match message {
+                        #(#dispatch)*
+                    }
+                }
+            }
+        };
+
+        (name, proc_macro::TokenStream::from(tokens))
+    }
+
+    // passes the message to the regions whose current state accepts it, the
+    // other regions are left as they are
+    fn generate_fn(&self, message: &Ident) -> syn::export::TokenStream2 {
+        let name = &self.name;
+        let fn_ident = Naming::default().handler(message);
+
+        let receivers = self
+            .regions
+            .iter()
+            .filter(|region| self.region(region).messages.contains(message))
+            .collect::<Vec<_>>();
+
+        let updates = receivers.iter().enumerate().map(|(i, region)| {
+            let field = region_field(region);
+            let region_fn = Naming {
+                handler_prefix: self.region(region).handler_prefix.value(),
+                ..Naming::default()
+            }
+            .handler(message);
+            let input = if i + 1 < receivers.len() {
+                quote!(input.clone())
+            } else {
+                quote!(input)
+            };
+            let patterns = self
+                .region(region)
+                .accepts
+                .iter()
+                .filter(|(m, _)| m == message)
+                .map(|(_, pattern)| quote!(state @ #pattern));
+
+            quote! {
+                let #field = match self.#field {
+                    #(#patterns)|* => state.#region_fn(#input),
+                    state => state,
+                };
+            }
+        });
+
+        let fields = self.regions.iter().map(region_field).collect::<Vec<_>>();
+        let unchanged = self
+            .regions
+            .iter()
+            .filter(|region| !receivers.contains(region))
+            .map(|region| {
+                let field = region_field(region);
+                quote!(let #field = self.#field;)
+            });
+
+        quote! {
+            pub fn #fn_ident(self, input: #message) -> #name {
+                #(#updates)*
+                #(#unchanged)*
+                #name { #(#fields),* }
+            }
+        }
+    }
+}
+
+fn region_field(region: &Ident) -> Ident {
+    Ident::new(&snake_case(&region.to_string()), Span::call_site())
+}
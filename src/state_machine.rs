@@ -0,0 +1,201 @@
+use machine::Machine;
+use methods::{MethodType, Methods};
+use transitions::Transitions;
+use syn::{
+    Error, Ident,
+    export::TokenStream2,
+    parse::{Parse, ParseStream, Result},
+};
+
+/// `state_machine! { enum Traffic { ... } transitions { ... } methods { ... } }`
+#[derive(Debug)]
+pub struct StateMachine {
+    pub machine: Machine,
+    pub transitions: Option<Transitions>,
+    pub methods: Option<Methods>,
+}
+
+impl Parse for StateMachine {
+    fn parse(input: ParseStream) -> Result<Self> {
+        // the definition is kept as written, to be given to the transitions
+        // and methods as their machine description
+        let mut definition = TokenStream2::new();
+        while !input.is_empty() && block_name(input).is_none() {
+            let token = input.step(|cursor| {
+                cursor
+                    .token_tree()
+                    .ok_or_else(|| cursor.error("expected the machine definition"))
+            })?;
+            definition.extend(Some(token));
+        }
+
+        let machine: Machine = syn::parse2(definition.clone())?;
+        let machine_name = machine.name().clone();
+
+        let mut transitions = None;
+        let mut methods = None;
+        while let Some(block) = block_name(input) {
+            let _: Ident = input.parse()?;
+
+            if block == "transitions" {
+                let mut options = TokenStream2::new();
+                if input.peek(syn::token::Paren) {
+                    let content;
+                    parenthesized!(content in input);
+                    let written: TokenStream2 = content.parse()?;
+                    if !written.is_empty() {
+                        options = quote!(#written,);
+                    }
+                }
+
+                let content;
+                braced!(content in input);
+                let table: TokenStream2 = content.parse()?;
+
+                if transitions.is_some() {
+                    return Err(Error::new(block.span(), "duplicate `transitions` block"));
+                }
+                let tokens = quote!({ #definition } #machine_name, #options [ #table ]);
+                transitions = Some(syn::parse2(tokens)?);
+            } else {
+                let content;
+                braced!(content in input);
+                let list: TokenStream2 = content.parse()?;
+
+                if methods.is_some() {
+                    return Err(Error::new(block.span(), "duplicate `methods` block"));
+                }
+                let tokens = quote!({ #definition } #machine_name, [ #list ]);
+                methods = Some(syn::parse2(tokens)?);
+            }
+        }
+
+        if !input.is_empty() {
+            return Err(input.error("expected a `transitions` or `methods` block"));
+        }
+
+        Ok(StateMachine {
+            machine,
+            transitions,
+            methods,
+        })
+    }
+}
+
+// `transitions` or `methods`, starting the next block
+fn block_name(input: ParseStream) -> Option<Ident> {
+    if !input.peek(Ident) {
+        return None;
+    }
+    input
+        .fork()
+        .parse::<Ident>()
+        .ok()
+        .filter(|ident| ident == "transitions" || ident == "methods")
+}
+
+impl StateMachine {
+    pub fn name(&self) -> &Ident {
+        self.machine.name()
+    }
+
+    /// the checks of the transitions and methods, and the methods clashing
+    /// with the ones generated for the transitions
+    pub fn validate(&self) -> Vec<Error> {
+        let mut errors = Vec::new();
+
+        if let Some(transitions) = &self.transitions {
+            errors.extend(transitions.validate());
+        }
+
+        if let Some(methods) = &self.methods {
+            errors.extend(methods.validate());
+
+            let naming = self.machine.naming();
+            let handlers = self
+                .transitions
+                .iter()
+                .flat_map(|transitions| transitions.transitions.iter())
+                .filter_map(|t| t.trigger.message())
+                .collect::<Vec<_>>();
+
+            for method in methods.methods.iter() {
+                if let MethodType::Fn(sig) = &method.method_type {
+                    if let Some(message) = handlers.iter().find(|m| naming.handler(m) == sig.ident) {
+                        errors.push(Error::new(
+                            sig.ident.span(),
+                            format!(
+                                "`{}` is already generated for the `{}` transitions",
+                                sig.ident, message
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+
+        errors
+    }
+
+    pub fn generate(&self) -> syn::export::TokenStream {
+        let mut stream = self.machine.generate().1;
+
+        if let Some(transitions) = &self.transitions {
+            stream.extend(transitions.generate().1);
+        }
+
+        if let Some(methods) = &self.methods {
+            stream.extend(methods.generate().1);
+        }
+
+        stream
+    }
+
+    /// the transitions graph, with a note listing the methods of each state
+    pub fn render_dot(&self) -> String {
+        let mut string = match &self.transitions {
+            Some(transitions) => {
+                let mut rendered = transitions.render_dot();
+                rendered.pop();
+                rendered
+            }
+            None => format!("digraph {} {{\n", self.name()),
+        };
+
+        let methods = match &self.methods {
+            Some(methods) => methods.methods.as_slice(),
+            None => &[],
+        };
+
+        let naming = self.machine.naming();
+        for state in self.machine.states() {
+            let names = methods
+                .iter()
+                .filter(|method| method.states.contains(state))
+                .map(|method| match &method.method_type {
+                    MethodType::Get(name, _) => format!("{}()", name),
+                    MethodType::Set(name, _) => format!("{}()", naming.setter(name)),
+                    MethodType::Fn(sig) => format!("{}()", sig.ident),
+                })
+                .collect::<Vec<_>>();
+
+            if names.is_empty() {
+                continue;
+            }
+
+            string.push_str(&format!(
+                "    {}_methods [ shape = note, label = \"{}\\l\" ];\n",
+                state,
+                names.join("\\l")
+            ));
+            string.push_str(&format!(
+                "    {} -> {}_methods [ style = dotted, arrowhead = none ];\n",
+                state, state
+            ));
+        }
+
+        string.push('}');
+
+        string
+    }
+}
@@ -1,8 +1,11 @@
 use std::collections::HashMap;
+use std::fmt;
 
-use case::CaseExt;
+use machine::Machine;
+use naming::{snake_case, Naming};
+use parallel::Region;
 use syn::{
-    Ident,
+    Block, Error, Expr, ExprClosure, FieldValue, FnArg, Ident, LitInt, Member, Path, Type,
     export::Span,
     parse::{Parse, ParseStream, Result},
     punctuated::Punctuated,
@@ -10,22 +13,113 @@ use syn::{
 
 #[derive(Debug)]
 pub struct Transitions {
+    pub machine: Option<Machine>,
+    /// the tables of the child machines, described by their `transitions!`
+    pub children: Vec<Region>,
     pub machine_name: Ident,
+    pub options: Options,
     pub transitions: Vec<Transition>,
 }
 
+#[derive(Debug, Default)]
+pub struct Options {
+    /// generate a `<State>Handlers` trait for each state
+    pub traits: bool,
+    /// generate a `<Machine>Runner` with a message queue
+    pub runner: bool,
+    /// `max_steps = 8`: completion transitions fired in a row before giving up
+    pub max_steps: Option<LitInt>,
+    /// `output = Command`: handlers also return the outputs of the transition
+    pub output: Option<Type>,
+    /// `effects = [StartTimer, Log]`: handlers also return the effects to perform
+    pub effects: Option<Vec<Ident>>,
+    /// generate a `<Machine>Typed<S>` wrapper checking the transitions at compile time
+    pub typed: bool,
+    /// `final_states`: generate `is_final()`, needed by the machines delegating to this one
+    pub final_states: bool,
+    /// `group = Pedestrian`: a table adding to the main one, with its own messages enum
+    pub group: Option<Ident>,
+    /// `groups = [Pedestrian]`: the groups included in the main messages enum
+    pub groups: Vec<Ident>,
+    /// `unchecked`: generate the table without the machine's description, for
+    /// machines whose `machine!` call is not in textual scope
+    pub unchecked: Option<Ident>,
+}
+
 #[derive(Debug)]
 pub struct Transition {
+    pub span: Span,
     pub start: Ident,
-    pub message: Ident,
+    pub trigger: Trigger,
     pub end: Vec<Ident>,
+    /// enum returned by the handler, with one variant per end state
+    pub outcome: Option<Path>,
+    /// handler body written in the table
+    pub body: Option<Body>,
+    /// `=> defer`: the message is kept until the machine is in a state accepting it
+    pub defer: bool,
+    /// `=> Connected::history`: back to the last state of a superstate
+    pub history: Option<History>,
+    /// `=> push Dialog`: the stack suspends the start state
+    pub push: bool,
+    /// `=> pop`: the stack goes back to the last suspended state
+    pub pop: bool,
+    /// `=> delegate handshake`: the message goes to the child machine in this field
+    pub delegate: Option<Ident>,
+}
+
+#[derive(Debug)]
+pub struct History {
+    pub superstate: Ident,
+    /// `deep_history` restores the last state, `history` the last direct child
+    pub deep: bool,
+}
+
+#[derive(Debug)]
+pub enum Trigger {
+    /// `(Green, Advance)`
+    Message(Ident),
+    /// `(Orange, after(Duration::from_secs(5)))`
+    After(Expr),
+    /// `(Validating, _)`: fires as soon as the state is entered
+    Completion,
+    /// `(Session, on_final(handshake))` or `(Session, on_error(handshake))`:
+    /// fires when the child machine in the field reaches a final or `Error` state
+    Child { field: Ident, error: bool },
+}
+
+#[derive(Debug)]
+pub enum Body {
+    /// `{}`: builds the end state, which must not have any member
+    Unit(Span),
+    /// `{ ... }`: with `self` and `input` in scope
+    Block(Block),
+    /// `|state, input| ...`
+    Closure(ExprClosure),
+    /// `{ y: true, ..carry }`: the end state's members, the missing ones
+    /// being copied from the start state if `carry` is set
+    Struct {
+        fields: Vec<FieldValue>,
+        carry: Option<Ident>,
+    },
 }
 
 impl Parse for Transitions {
     fn parse(input: ParseStream) -> Result<Self> {
+        // the children's descriptions come before the machine's
+        let mut children = Vec::new();
+        while input.peek(syn::token::Brace) && input.peek2(syn::token::Brace) {
+            let content;
+            braced!(content in input);
+            children.push(content.parse()?);
+        }
+
+        let machine = parse_description(input)?;
         let machine_name: Ident = input.parse()?;
         let _: Token![,] = input.parse()?;
 
+        let options: Options = input.parse()?;
+
         let content;
         bracketed!(content in input);
 
@@ -37,23 +131,208 @@ impl Parse for Transitions {
         let transitions: Vec<_> = punctuated.into_iter().collect();
 
         Ok(Transitions {
+            machine,
+            children,
             machine_name,
+            options,
             transitions,
         })
     }
 }
 
+impl Parse for Options {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut options = Options::default();
+
+        while !input.peek(syn::token::Bracket) {
+            let option: Ident = input.parse()?;
+
+            if option == "traits" {
+                options.traits = true;
+            } else if option == "runner" {
+                options.runner = true;
+            } else if option == "typed" {
+                options.typed = true;
+            } else if option == "final_states" {
+                options.final_states = true;
+            } else if option == "unchecked" {
+                options.unchecked = Some(option);
+            } else if option == "group" {
+                let _: Token![=] = input.parse()?;
+                options.group = Some(input.parse()?);
+            } else if option == "groups" {
+                let _: Token![=] = input.parse()?;
+                let content;
+                bracketed!(content in input);
+                let groups: Punctuated<Ident, Token![,]> = content.parse_terminated(Ident::parse)?;
+                options.groups = groups.into_iter().collect();
+            } else if option == "max_steps" {
+                let _: Token![=] = input.parse()?;
+                options.max_steps = Some(input.parse()?);
+            } else if option == "output" {
+                let _: Token![=] = input.parse()?;
+                options.output = Some(input.parse()?);
+            } else if option == "effects" {
+                let _: Token![=] = input.parse()?;
+                let content;
+                bracketed!(content in input);
+                let effects: Punctuated<Ident, Token![,]> = content.parse_terminated(Ident::parse)?;
+                options.effects = Some(effects.into_iter().collect());
+            } else {
+                return Err(Error::new(
+                    option.span(),
+                    format!("unknown transitions option `{}`", option),
+                ));
+            }
+
+            let _: Token![,] = input.parse()?;
+        }
+
+        if let (Some(output), Some(_)) = (&options.output, &options.effects) {
+            return Err(Error::new_spanned(
+                output,
+                "`output` and `effects` cannot be used together",
+            ));
+        }
+
+        // the group tables only add messages: the items generated once per
+        // machine come from the main table
+        if let Some(group) = &options.group {
+            if !options.groups.is_empty() {
+                return Err(Error::new(
+                    group.span(),
+                    "a group table cannot include other groups",
+                ));
+            }
+            if options.traits
+                || options.runner
+                || options.typed
+                || options.final_states
+                || options.effects.is_some()
+            {
+                return Err(Error::new(
+                    group.span(),
+                    "a group table cannot use the `traits`, `runner`, `typed`, `final_states` or `effects` options",
+                ));
+            }
+        }
+
+        Ok(options)
+    }
+}
+
 impl Parse for Transition {
     fn parse(input: ParseStream) -> Result<Self> {
         let left;
-        parenthesized!(left in input);
+        let paren = parenthesized!(left in input);
 
         let start: Ident = left.parse()?;
         let _: Token![,] = left.parse()?;
-        let message: Ident = left.parse()?;
+        let trigger: Trigger = left.parse()?;
 
         let _: Token![=>] = input.parse()?;
 
+        if input.peek(Ident) && input.fork().parse::<Ident>()? == "defer" {
+            let defer: Ident = input.parse()?;
+            if trigger.message().is_none() {
+                return Err(Error::new(defer.span(), "only messages can be deferred"));
+            }
+
+            return Ok(Transition {
+                span: paren.span,
+                start,
+                trigger,
+                end: Vec::new(),
+                outcome: None,
+                body: None,
+                defer: true,
+                history: None,
+                push: false,
+                pop: false,
+                delegate: None,
+            });
+        }
+
+        if input.peek(Ident) && input.peek2(Ident) && input.fork().parse::<Ident>()? == "delegate" {
+            let delegate: Ident = input.parse()?;
+            if trigger.message().is_none() {
+                return Err(Error::new(delegate.span(), "only messages can be delegated"));
+            }
+
+            return Ok(Transition {
+                span: paren.span,
+                start,
+                trigger,
+                end: Vec::new(),
+                outcome: None,
+                body: None,
+                defer: false,
+                history: None,
+                push: false,
+                pop: false,
+                delegate: Some(input.parse()?),
+            });
+        }
+
+        if input.peek(Ident) && input.fork().parse::<Ident>()? == "pop" {
+            let pop: Ident = input.parse()?;
+            if trigger.message().is_none() {
+                return Err(Error::new(pop.span(), "only messages can pop the stack"));
+            }
+
+            return Ok(Transition {
+                span: paren.span,
+                start,
+                trigger,
+                end: Vec::new(),
+                outcome: None,
+                body: None,
+                defer: false,
+                history: None,
+                push: false,
+                pop: true,
+                delegate: None,
+            });
+        }
+
+        if input.peek(Ident) && input.peek2(Token![::]) {
+            let superstate: Ident = input.parse()?;
+            let _: Token![::] = input.parse()?;
+            let kind: Ident = input.parse()?;
+            let deep = if kind == "history" {
+                false
+            } else if kind == "deep_history" {
+                true
+            } else {
+                return Err(Error::new(
+                    kind.span(),
+                    "expected `history` or `deep_history`",
+                ));
+            };
+
+            return Ok(Transition {
+                span: paren.span,
+                start,
+                trigger,
+                end: Vec::new(),
+                outcome: None,
+                body: None,
+                defer: false,
+                history: Some(History { superstate, deep }),
+                push: false,
+                pop: false,
+                delegate: None,
+            });
+        }
+
+        let push = input.peek(Ident) && input.peek2(Ident) && input.fork().parse::<Ident>()? == "push";
+        if push {
+            let push: Ident = input.parse()?;
+            if trigger.message().is_none() {
+                return Err(Error::new(push.span(), "only messages can push on the stack"));
+            }
+        }
+
         let end = match input.parse::<Ident>() {
             Ok(i) => vec![i],
             Err(_) => {
@@ -70,33 +349,543 @@ impl Parse for Transition {
             }
         };
 
+        let outcome = if input.peek(Token![as]) {
+            let _: Token![as] = input.parse()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+
+        let body = if input.peek(syn::token::Brace) && is_struct_body(input) {
+            if end.len() != 1 || outcome.is_some() {
+                return Err(input.error("a struct body can only build a single end state"));
+            }
+            Some(parse_struct_body(input)?)
+        } else if input.peek(syn::token::Brace) {
+            let block: Block = input.parse()?;
+            if block.stmts.is_empty() {
+                if end.len() != 1 || outcome.is_some() {
+                    return Err(Error::new(
+                        block.brace_token.span,
+                        "an empty body can only build a single end state",
+                    ));
+                }
+                Some(Body::Unit(block.brace_token.span))
+            } else {
+                Some(Body::Block(block))
+            }
+        } else if input.peek(Token![|]) || input.peek(Token![||]) || input.peek(Token![move]) {
+            Some(Body::Closure(input.parse()?))
+        } else {
+            None
+        };
+
         Ok(Transition {
+            span: paren.span,
             start,
-            message,
+            trigger,
             end,
+            outcome,
+            body,
+            defer: false,
+            history: None,
+            push,
+            pop: false,
+            delegate: None,
         })
     }
 }
 
+impl Parse for Trigger {
+    fn parse(input: ParseStream) -> Result<Self> {
+        if input.peek(Token![_]) {
+            let _: Token![_] = input.parse()?;
+            return Ok(Trigger::Completion);
+        }
+
+        let message: Ident = input.parse()?;
+
+        if message == "after" && input.peek(syn::token::Paren) {
+            let content;
+            parenthesized!(content in input);
+            Ok(Trigger::After(content.parse()?))
+        } else if (message == "on_final" || message == "on_error") && input.peek(syn::token::Paren) {
+            let content;
+            parenthesized!(content in input);
+            Ok(Trigger::Child {
+                field: content.parse()?,
+                error: message == "on_error",
+            })
+        } else {
+            Ok(Trigger::Message(message))
+        }
+    }
+}
+
 impl Transitions {
+    /// checks that the states are declared in the machine, and that each
+    /// (state, message) pair appears at most once in the table
+    pub fn validate(&self) -> Vec<Error> {
+        let mut errors = Vec::new();
+
+        if let Some(machine) = &self.machine {
+            for transition in self.transitions.iter() {
+                let mut declared = true;
+                if !machine.has_state(&transition.start) && !machine.is_superstate(&transition.start) {
+                    errors.push(undeclared_state(&transition.start, machine.name()));
+                    declared = false;
+                }
+                for state in transition.end.iter() {
+                    if machine.is_superstate(state) {
+                        errors.push(Error::new(
+                            state.span(),
+                            format!(
+                                "`{}` is a superstate, the transition must end in one of its states",
+                                state
+                            ),
+                        ));
+                        declared = false;
+                    } else if !machine.has_state(state) {
+                        errors.push(undeclared_state(state, machine.name()));
+                        declared = false;
+                    }
+                }
+
+                if declared {
+                    errors.extend(transition.validate_struct_body(machine));
+                }
+
+                if let Some(history) = &transition.history {
+                    errors.extend(self.validate_history(machine, history));
+                    if self.options.typed {
+                        errors.push(Error::new(
+                            transition.span,
+                            "the `typed` option cannot be used with history targets, as the typed states do not record the history",
+                        ));
+                    }
+                }
+
+                if (transition.push || transition.pop)
+                    && (self.options.runner || self.output().is_some())
+                {
+                    errors.push(Error::new(
+                        transition.span,
+                        "`push` and `pop` cannot be used with a runner or outputs",
+                    ));
+                }
+
+                if let Some(field) = &transition.delegate {
+                    errors.extend(self.validate_delegate(
+                        machine,
+                        &transition.start,
+                        transition.trigger.message(),
+                        field,
+                    ));
+                }
+
+                if self.options.group.is_some()
+                    && (transition.trigger.message().is_none()
+                        || transition.history.is_some()
+                        || transition.push
+                        || transition.pop)
+                {
+                    errors.push(Error::new(
+                        transition.span,
+                        "a group table can only have message transitions, without history, `push` or `pop`",
+                    ));
+                }
+
+                if let Trigger::Child { field, error } = &transition.trigger {
+                    let delegated = self.transitions.iter().any(|t| {
+                        t.start == transition.start && t.delegate.as_ref() == Some(field)
+                    });
+                    if !delegated {
+                        errors.push(Error::new(
+                            field.span(),
+                            format!(
+                                "`{}` does not delegate any message to `{}`",
+                                transition.start, field
+                            ),
+                        ));
+                    }
+
+                    match self.child(&transition.start, field) {
+                        Some(child) if !error && !child.final_states => {
+                            errors.push(Error::new(
+                                field.span(),
+                                format!(
+                                    "`{}` has no final states, its table needs the `final_states` option",
+                                    child.name
+                                ),
+                            ));
+                        }
+                        _ => {}
+                    }
+                }
+
+                if transition.defer && !self.options.runner {
+                    errors.push(Error::new(
+                        transition.span,
+                        "deferring messages requires the `runner` option",
+                    ));
+                }
+
+                if let Some(Body::Closure(closure)) = &transition.body {
+                    if closure.inputs.len() > 1 + self.handler_args(transition, transition.span).len() {
+                        errors.push(Error::new_spanned(
+                            &closure.inputs,
+                            "too many arguments for the handler closure",
+                        ));
+                    }
+                }
+            }
+        } else if let Some(unchecked) = &self.options.unchecked {
+            errors.extend(self.validate_unchecked(unchecked));
+        }
+
+        for (i, transition) in self.transitions.iter().enumerate() {
+            let previous = self.transitions[..i]
+                .iter()
+                .find(|t| t.start == transition.start && t.trigger.same(&transition.trigger));
+
+            if let Some(previous) = previous {
+                let message = if render_end(previous) == render_end(transition) {
+                    format!(
+                        "duplicate transition for `({}, {})`",
+                        transition.start, transition.trigger
+                    )
+                } else {
+                    format!(
+                        "conflicting transitions for `({}, {})`: `{}` and `{}`",
+                        transition.start,
+                        transition.trigger,
+                        render_end(previous),
+                        render_end(transition)
+                    )
+                };
+
+                errors.push(Error::new(transition.span, message));
+                errors.push(Error::new(
+                    previous.span,
+                    format!(
+                        "`({}, {})` was first declared here",
+                        previous.start, previous.trigger
+                    ),
+                ));
+            }
+        }
+
+        errors.extend(self.validate_timer_names());
+
+        errors
+    }
+
+    // the timed transitions call `on_timeout` on the states and add `on_tick`
+    // to the machine, so no message can have these handlers
+    fn validate_timer_names(&self) -> Vec<Error> {
+        let mut errors = Vec::new();
+        if !self.transitions.iter().any(|t| t.trigger.is_after()) {
+            return errors;
+        }
+
+        let naming = self.naming();
+        let reserved = [naming.handler_suffix("timeout"), Ident::new("on_tick", Span::call_site())];
+        let mut seen = Vec::new();
+
+        for message in self.transitions.iter().filter_map(|t| t.trigger.message()) {
+            let handler = naming.handler(message);
+            if reserved.contains(&handler) && !seen.contains(&message) {
+                seen.push(message);
+                errors.push(Error::new(
+                    message.span(),
+                    format!(
+                        "the handler of `{}` would be `{}`, which is reserved for the timed transitions",
+                        message, handler
+                    ),
+                ));
+            }
+        }
+
+        errors
+    }
+
+    // the features that read the machine's description
+    fn validate_unchecked(&self, unchecked: &Ident) -> Vec<Error> {
+        let mut errors = Vec::new();
+
+        if self.options.typed {
+            errors.push(Error::new(
+                unchecked.span(),
+                "the `typed` option needs the machine's description, which `unchecked` skips",
+            ));
+        }
+
+        for transition in self.transitions.iter() {
+            let uses_description = transition.history.is_some()
+                || transition.delegate.is_some()
+                || matches!(transition.body, Some(Body::Struct { carry: Some(_), .. }));
+            if uses_description {
+                errors.push(Error::new(
+                    transition.span,
+                    "history targets, delegation and `..carry` need the machine's description, which `unchecked` skips",
+                ));
+            }
+        }
+
+        errors
+    }
+
+    // the delegating state must hold a child machine in the field
+    fn validate_delegate(
+        &self,
+        machine: &Machine,
+        start: &Ident,
+        message: Option<&Ident>,
+        field: &Ident,
+    ) -> Vec<Error> {
+        if machine.is_superstate(start) {
+            return vec![Error::new(
+                start.span(),
+                format!("`{}` is a superstate, only states can delegate messages", start),
+            )];
+        }
+
+        if child_type(machine, start, field).is_none() {
+            return vec![Error::new(
+                field.span(),
+                format!("`{}` has no field `{}` holding a machine", start, field),
+            )];
+        }
+
+        let child = match self.child(start, field) {
+            Some(child) => child,
+            None => return Vec::new(),
+        };
+
+        let mut errors = Vec::new();
+        if !child.plain {
+            errors.push(Error::new(
+                field.span(),
+                format!(
+                    "`{}`, the machine in `{}`, cannot use a context, a runner, outputs or history",
+                    child.name, field
+                ),
+            ));
+        }
+
+        if let Some(message) = message {
+            if !child.messages.contains(message) {
+                errors.push(Error::new(
+                    message.span(),
+                    format!("`{}` has no transition for `{}`", child.name, message),
+                ));
+            }
+        }
+
+        errors
+    }
+
+    fn validate_history(&self, machine: &Machine, history: &History) -> Vec<Error> {
+        let superstate = &history.superstate;
+        if !machine.is_superstate(superstate) {
+            return vec![Error::new(
+                superstate.span(),
+                format!("`{}` is not a superstate of machine `{}`", superstate, machine.name()),
+            )];
+        }
+
+        // entered when there is no history yet, or by a shallow history
+        // when the last child was a superstate
+        let mut initial = vec![superstate];
+        if !history.deep {
+            initial.extend(
+                machine
+                    .children(superstate)
+                    .into_iter()
+                    .filter(|child| machine.is_superstate(child)),
+            );
+        }
+
+        initial
+            .into_iter()
+            .filter_map(|superstate| {
+                let state = machine.leaves(superstate)[0];
+                match machine.fields(state) {
+                    Some(fields) if fields.iter().next().is_some() => Some(Error::new(
+                        history.superstate.span(),
+                        format!(
+                            "`{}`, the first state of `{}`, must have no members to be entered through its history",
+                            state, superstate
+                        ),
+                    )),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
     pub fn render_dot(&self) -> String {
-        let mut string = format!("digraph {} {{\n", self.machine_name.to_string());
+        let mut string = format!("digraph {} {{\n", self.machine_name);
+
+        // states with an output are labeled with it
+        if let Some(machine) = &self.machine {
+            for state in machine.states() {
+                if let Some(output) = machine.output(state) {
+                    let output = quote!(#output).to_string().replace(' ', "");
+                    string.push_str(&format!(
+                        "    {} [ label = \"{} / {}\" ];\n",
+                        state, state, output.replace('"', "\\\"")
+                    ));
+                }
+            }
+
+            // superstates are drawn as clusters around their states
+            let superstates = machine.superstates();
+            if !superstates.is_empty() {
+                string.push_str("    compound = true;\n");
+            }
+            for superstate in superstates {
+                if machine.ancestors(superstate).is_empty() {
+                    render_cluster(machine, superstate, 1, &mut string);
+                }
+            }
+        }
+
+        string.push_str(&self.render_dot_edges());
+        string.push('}');
+
+        string
+    }
+
+    // the edges of the graph, without the states and clusters, which a group
+    // table leaves to the main one
+    fn render_dot_edges(&self) -> String {
+        let mut string = String::new();
+
+        // the handlers return the outputs, unlike the delegated, pop and history edges
+        let output = self
+            .output()
+            .map(|output| quote!(#output).to_string().replace(' ', ""));
 
         let mut edges = Vec::new();
         for transition in self.transitions.iter() {
             for state in transition.end.iter() {
-                edges.push((&transition.start, &transition.message, state));
+                let mut label = match transition.trigger {
+                    Trigger::Completion => String::new(),
+                    _ => transition.trigger.to_string(),
+                };
+                if let Some(output) = &output {
+                    label = format!("{} / {}", label, output).trim_start().to_string();
+                }
+                if transition.push {
+                    label = format!("{} (push)", label);
+                }
+                edges.push((&transition.start, label, state));
+            }
+        }
+
+        // delegated messages leave the state unchanged
+        for transition in self.transitions.iter().filter(|t| t.delegate.is_some()) {
+            string.push_str(&format!(
+                "    {} -> {} [ label = \"{} (delegate)\", style = dashed ];\n",
+                transition.start, transition.start, transition.trigger
+            ));
+        }
+
+        // pop edges go back to the states that can be suspended
+        for transition in self.transitions.iter().filter(|t| t.pop) {
+            let mut suspended: Vec<&Ident> = Vec::new();
+            for push in self.transitions.iter().filter(|t| t.push) {
+                if !suspended.contains(&&push.start) {
+                    suspended.push(&push.start);
+                }
+            }
+            for state in suspended {
+                string.push_str(&format!(
+                    "    {} -> {} [ label = \"{} (pop)\", style = dashed ];\n",
+                    transition.start,
+                    state,
+                    transition.trigger.to_string().replace('"', "\\\"")
+                ));
+            }
+        }
+
+        // history edges go to the first state of the superstate, clipped to the cluster
+        if let Some(machine) = &self.machine {
+            for transition in self.transitions.iter() {
+                if let Some(history) = &transition.history {
+                    let superstate = &history.superstate;
+                    if !machine.is_superstate(superstate) {
+                        continue;
+                    }
+                    let kind = if history.deep { "H*" } else { "H" };
+                    string.push_str(&format!(
+                        "    {} -> {} [ label = \"{} ({})\", lhead = cluster_{} ];\n",
+                        transition.start,
+                        machine.leaves(superstate)[0],
+                        transition.trigger.to_string().replace('"', "\\\""),
+                        kind,
+                        superstate
+                    ));
+                }
             }
         }
 
         for edge in edges.iter() {
+            // edges leaving a superstate start from one of its states, clipped to the cluster
+            let (start, tail) = match &self.machine {
+                Some(machine) if machine.is_superstate(edge.0) => (
+                    machine.leaves(edge.0)[0],
+                    format!(", ltail = cluster_{}", edge.0),
+                ),
+                _ => (edge.0, String::new()),
+            };
+
             string.push_str(&format!(
-                "    {} -> {} [ label = \"{}\" ];\n",
-                edge.0, edge.2, edge.1
+                "    {} -> {} [ label = \"{}\"{} ];\n",
+                start, edge.2, edge.1.replace('"', "\\\""), tail
             ))
         }
 
-        string.push_str("}");
+        string
+    }
+
+    /// the graph of this table merged with `existing`, the one last written
+    /// for the machine: a group table replaces its own section, and the main
+    /// table keeps the sections of the groups it includes
+    pub fn merge_dot(&self, existing: Option<&str>) -> String {
+        let header = format!("digraph {} {{\n", self.machine_name);
+        let existing = existing.filter(|existing| existing.starts_with(&header));
+        let sections = existing.map(dot_sections).unwrap_or_default();
+
+        let (mut string, sections) = match &self.options.group {
+            // the lines of the main table are kept, and the group's section replaced
+            Some(group) => {
+                let body = self.render_dot_edges();
+                let mut sections = sections
+                    .into_iter()
+                    .filter(|(name, _)| name != &group.to_string())
+                    .collect::<Vec<_>>();
+                sections.push((
+                    group.to_string(),
+                    format!("    // group {}\n{}    // end of group {}\n", group, body, group),
+                ));
+                (existing.map(main_dot_lines).unwrap_or(header), sections)
+            }
+            None => {
+                let sections = sections
+                    .into_iter()
+                    .filter(|(name, _)| self.options.groups.iter().any(|group| group == name))
+                    .collect::<Vec<_>>();
+                let rendered = self.render_dot();
+                (rendered[..rendered.len() - 1].to_string(), sections)
+            }
+        };
+
+        for (_, section) in sections {
+            string.push_str(&section);
+        }
+        string.push('}');
 
         string
     }
@@ -108,33 +897,89 @@ impl Transitions {
 
         let mut messages = HashMap::new();
         for t in self.transitions.iter() {
-            let entry = messages.entry(&t.message).or_insert(Vec::new());
-            entry.push((&t.start, &t.end));
+            if let Some(message) = t.trigger.message() {
+                let entry = messages.entry(message).or_insert(Vec::new());
+                entry.push(t);
+            }
         }
 
         stream.extend(self.generate_messages_enum(&messages));
         stream.extend(self.generate_impl(&messages));
+        stream.extend(self.generate_handlers());
+
+        if let Some(group) = &self.options.group {
+            stream.extend(self.generate_group(group, &messages));
+            return (machine_name, stream);
+        }
+
+        if self.options.final_states {
+            stream.extend(self.generate_is_final());
+        }
+
+        if !self.options.groups.is_empty() && !self.options.runner {
+            stream.extend(self.generate_dispatch(&messages));
+        }
+
+        if self.transitions.iter().any(|t| t.trigger.is_after()) {
+            stream.extend(self.generate_timers());
+        }
+
+        if self.has_completions() {
+            stream.extend(self.generate_completions());
+        }
+
+        if self.options.traits {
+            stream.extend(self.generate_traits());
+        }
+
+        if self.options.runner {
+            stream.extend(self.generate_runner(&messages));
+        }
+
+        if let Some(effects) = &self.options.effects {
+            stream.extend(self.generate_effects(effects));
+        }
+
+        if !self.history_superstates().is_empty() {
+            stream.extend(self.generate_history());
+        }
+
+        if self.transitions.iter().any(|t| t.push || t.pop) {
+            stream.extend(self.generate_stack(&messages));
+        }
+
+        if self.options.typed {
+            stream.extend(self.generate_typed());
+        }
+
+        stream.extend(self.generate_description(&messages));
 
         (machine_name, stream)
     }
 
     fn generate_messages_enum(
         &self,
-        messages: &HashMap<&syn::Ident, Vec<(&syn::Ident, &Vec<syn::Ident>)>>,
+        messages: &HashMap<&syn::Ident, Vec<&Transition>>,
     ) -> syn::export::TokenStream {
-        let machine_name = &self.machine_name;
-
+        let vis = self.vis();
         // create an enum from the messages
-        let message_enum_ident = Ident::new(
-            &format!("{}Messages", &machine_name.to_string()),
-            Span::call_site(),
-        );
-        let variants_names = &messages.keys().collect::<Vec<_>>();
-        let structs_names = variants_names.clone();
+        let message_enum_ident = self.messages_enum();
+        let mut variants_names = messages.keys().cloned().collect::<Vec<_>>();
+        let mut structs_names = variants_names
+            .iter()
+            .map(|message| quote!(#message))
+            .collect::<Vec<_>>();
+
+        // the messages of the groups are wrapped in their own enum
+        for group in self.options.groups.iter() {
+            variants_names.push(group);
+            let group_enum = self.group_enum(group);
+            structs_names.push(quote!(#group_enum));
+        }
 
         let tokens = quote! {
           #[derive(Clone,Debug,PartialEq)]
-          pub enum #message_enum_ident {
+          #vis enum #message_enum_ident {
             #(#variants_names(#structs_names)),*
           }
         };
@@ -145,55 +990,2351 @@ impl Transitions {
     fn generate_fn(
         &self,
         message: &syn::Ident,
-        moves: &[(&syn::Ident, &Vec<syn::Ident>)],
+        moves: &[&Transition],
     ) -> syn::export::TokenStream2 {
+        let fn_ident = self.naming().handler(message);
+        let next = self.generate_next(moves);
+        let ret = self.machine_output();
+        let params = self.entry_params(self.needs_history(moves));
+
+        quote! {
+            pub fn #fn_ident(self #params, input: #message) -> #ret {
+                #next
+            }
+        }
+    }
+
+    // the next state for the message `input`, from the transitions it triggers
+    fn generate_next(&self, moves: &[&Transition]) -> syn::export::TokenStream2 {
         let machine_name = &self.machine_name;
 
-        let fn_ident = Ident::new(
-            &format!("on_{}", &message.to_string().to_snake()),
-            Span::call_site(),
-        );
+        let mv: Vec<_> = moves
+            .iter()
+            .filter(|t| !t.defer && !t.pop)
+            .map(|t| self.generate_move(t))
+            .collect();
 
-        let mv: Vec<_> = moves.iter().map(|(start, end)| {
-            if end.len() == 1 {
-                let end_state = &end[0];
-                quote!{
-                    #machine_name::#start(state) => #machine_name::#end_state(state.#fn_ident(input)),
-                }
-            } else {
-                quote!{
-                    #machine_name::#start(state) => state.#fn_ident(input),
-                }
+        let error = self.machine_error();
+        let error = self.without_output(quote!(#machine_name::#error));
+        let next = quote! {
+            match self {
+            #(#mv)*
+                _ => #error,
             }
-        }).collect();
+        };
+        self.completed(self.recorded(moves, next))
+    }
+
+    // match arms calling the start state's handler and wrapping its result,
+    // one for each state if the transition starts from a superstate
+    fn generate_move(&self, t: &Transition) -> syn::export::TokenStream2 {
+        let arms = self
+            .start_states(t)
+            .into_iter()
+            .map(|leaf| self.generate_arm(t, &leaf));
+        quote!(#(#arms)*)
+    }
+
+    fn generate_arm(&self, t: &Transition, leaf: &Ident) -> syn::export::TokenStream2 {
+        let machine_name = &self.machine_name;
+
+        if let Some(history) = &t.history {
+            let restored = self.without_output(self.generate_restore(history));
+            return quote! {
+                #machine_name::#leaf(_) => #restored,
+            };
+        }
 
+        if let Some(field) = &t.delegate {
+            return self.generate_delegate(t, field);
+        }
+
+        let next = self.generate_call(t, leaf);
         quote! {
-            pub fn #fn_ident(self, input: #message) -> #machine_name {
-                match self {
-                #(#mv)*
-                    _ => #machine_name::Error,
-                }
-            }
+            #machine_name::#leaf(state) => #next,
         }
     }
 
-    fn generate_impl(
-        &self,
-        messages: &HashMap<&syn::Ident, Vec<(&syn::Ident, &Vec<syn::Ident>)>>,
-    ) -> syn::export::TokenStream {
+    // passes the message to the child machine, then fires the `on_error` or
+    // `on_final` transition if the child reached such a state
+    fn generate_delegate(&self, t: &Transition, field: &Ident) -> syn::export::TokenStream2 {
         let machine_name = &self.machine_name;
+        let start = &t.start;
 
-        let functions = messages
-            .iter()
-            .map(|(message, moves)| self.generate_fn(message, moves.as_slice()))
-            .collect::<Vec<_>>();
+        // the child's `on_*` method is named with its own handler prefix
+        let naming = match self.child(start, field) {
+            Some(child) => Naming {
+                handler_prefix: child.handler_prefix.value(),
+                ..Naming::default()
+            },
+            None => self.naming(),
+        };
+        let fn_ident = t.trigger.handler_name(&naming);
 
-        let tokens = quote! {
-          impl #machine_name {
-            #(#functions)*
-          }
+        let fired = |error: bool| {
+            self.transitions
+                .iter()
+                .find(|o| match &o.trigger {
+                    Trigger::Child { field: f, error: e } => {
+                        &o.start == start && f == field && *e == error
+                    }
+                    _ => false,
+                })
+                .map(|o| self.generate_call(o, start))
         };
 
-        proc_macro::TokenStream::from(tokens)
-    }
+        let unchanged = self.without_output(quote!(#machine_name::#start(state)));
+        let mut next = unchanged;
+        if let Some(on_final) = fired(false) {
+            next = quote! {
+                if state.#field.is_final() {
+                    #on_final
+                } else {
+                    #next
+                }
+            };
+        }
+        if let Some(on_error) = fired(true) {
+            next = quote! {
+                if state.#field.is_error() {
+                    #on_error
+                } else {
+                    #next
+                }
+            };
+        }
+
+        quote! {
+            #machine_name::#start(mut state) => {
+                state.#field = state.#field.#fn_ident(input);
+                #next
+            }
+        }
+    }
+
+    // the next state built by the handler of `t`, called on `state`
+    fn generate_call(&self, t: &Transition, leaf: &Ident) -> syn::export::TokenStream2 {
+        let machine_name = &self.machine_name;
+        let end = &t.end;
+        let call = self.handler_call(t, leaf);
+
+        if self.output().is_some() {
+            if let Some(outcome) = &t.outcome {
+                let arms = end.iter().map(|end_state| {
+                    quote!((#outcome::#end_state(state), output) => (#machine_name::#end_state(state), output),)
+                });
+
+                quote!{
+                    match #call {
+                        #(#arms)*
+                    }
+                }
+            } else if end.len() == 1 {
+                let end_state = &end[0];
+                quote!{
+                    {
+                        let (state, output) = #call;
+                        (#machine_name::#end_state(state), output)
+                    }
+                }
+            } else {
+                call
+            }
+        } else if let Some(outcome) = &t.outcome {
+            let arms = end.iter().map(|end_state| {
+                quote!(#outcome::#end_state(state) => #machine_name::#end_state(state),)
+            });
+
+            quote!{
+                match #call {
+                    #(#arms)*
+                }
+            }
+        } else if end.len() == 1 {
+            let end_state = &end[0];
+            quote!(#machine_name::#end_state(#call))
+        } else {
+            call
+        }
+    }
+
+    // the call to the handler of `t`, on the value of `leaf` bound to `state`
+    fn handler_call(&self, t: &Transition, leaf: &Ident) -> syn::export::TokenStream2 {
+        let start = &t.start;
+        let fn_ident = t.trigger.handler_name(&self.naming());
+        let args = self.handler_args(t, Span::call_site());
+
+        // the handler is called on the superstate's value
+        let value = match &self.machine {
+            Some(machine) => machine.wrap(leaf, start, quote!(state)),
+            None => quote!(state),
+        };
+
+        if self.options.traits {
+            let trait_name = handlers_trait_name(&self.machine_name, start);
+            quote!(#trait_name::#fn_ident(#value #(, #args)*))
+        } else {
+            quote!((#value).#fn_ident(#(#args),*))
+        }
+    }
+
+    // `is_final()`: whether the machine is in a state without any transition
+    fn generate_is_final(&self) -> syn::export::TokenStream {
+        let machine_name = &self.machine_name;
+        let machine = match &self.machine {
+            Some(machine) => machine,
+            None => return proc_macro::TokenStream::new(),
+        };
+
+        let mut started = Vec::new();
+        for t in self.transitions.iter().filter(|t| !t.defer) {
+            started.extend(self.start_states(t));
+        }
+        let finals = machine
+            .states()
+            .filter(|state| !started.contains(state))
+            .map(|state| quote!(#machine_name::#state(_)))
+            .collect::<Vec<_>>();
+
+        // the states left by the transitions of the groups are not final either
+        let groups = self.options.groups.iter().map(|group| {
+            let leaves_ident = Ident::new(
+                &format!("__leaves_{}", snake_case(&group.to_string())),
+                Span::call_site(),
+            );
+            quote!(&& !self.#leaves_ident())
+        });
+        let body = if finals.is_empty() {
+            quote!(false)
+        } else {
+            quote!(matches!(self, #(#finals)|*) #(#groups)*)
+        };
+
+        let tokens = quote! {
+            impl #machine_name {
+                /// whether no transition leaves the current state, `Error` excepted
+                pub fn is_final(&self) -> bool {
+                    #body
+                }
+            }
+        };
+
+        proc_macro::TokenStream::from(tokens)
+    }
+
+    // the superstates used as history targets
+    fn history_superstates(&self) -> Vec<&Ident> {
+        let mut superstates: Vec<&Ident> = Vec::new();
+        for t in self.transitions.iter() {
+            if let Some(history) = &t.history {
+                if !superstates.contains(&&history.superstate) {
+                    superstates.push(&history.superstate);
+                }
+            }
+        }
+        superstates
+    }
+
+    fn history_struct(&self) -> Ident {
+        Ident::new(&format!("{}History", self.machine_name), Span::call_site())
+    }
+
+    // the state restored by a history transition
+    fn generate_restore(&self, history: &History) -> syn::export::TokenStream2 {
+        let machine_name = &self.machine_name;
+        let machine = self.machine.as_ref().unwrap();
+        let superstate = &history.superstate;
+        let field = history_field(superstate);
+
+        let initial = |superstate: &Ident| {
+            let state = machine.leaves(superstate)[0];
+            let path = machine.path(state);
+            quote!(#machine_name::#state(#path {}))
+        };
+
+        let arms = if history.deep {
+            machine
+                .leaves(superstate)
+                .into_iter()
+                .map(|leaf| {
+                    let pattern = machine.wrap(leaf, superstate, quote!(state));
+                    quote!(Some(#pattern) => #machine_name::#leaf(state),)
+                })
+                .collect::<Vec<_>>()
+        } else {
+            machine
+                .children(superstate)
+                .into_iter()
+                .map(|child| {
+                    let superstate = machine.path(superstate);
+                    if machine.is_superstate(child) {
+                        let state = initial(child);
+                        quote!(Some(#superstate::#child(_)) => #state,)
+                    } else {
+                        quote!(Some(#superstate::#child(state)) => #machine_name::#child(state),)
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        let none = initial(superstate);
+
+        quote! {
+            match history.#field.take() {
+                #(#arms)*
+                None => #none,
+            }
+        }
+    }
+
+    // the last state of each history superstate, and the method recording it
+    fn generate_history(&self) -> syn::export::TokenStream {
+        let vis = self.vis();
+        let machine_name = &self.machine_name;
+        let machine = self.machine.as_ref().unwrap();
+        let history = self.history_struct();
+        let attributes = machine.attributes();
+
+        let superstates = self.history_superstates();
+        let fields = superstates.iter().map(|superstate| {
+            let field = history_field(superstate);
+            let path = machine.path(superstate);
+            quote!(#field: Option<#path>)
+        });
+        let empty = superstates.iter().map(|superstate| {
+            let field = history_field(superstate);
+            quote!(#field: None)
+        });
+
+        let records = superstates.iter().map(|superstate| {
+            let field = history_field(superstate);
+            let leaves = machine.leaves(superstate);
+            let inside = leaves.iter().map(|leaf| quote!(#machine_name::#leaf(_)));
+            let arms = leaves.iter().map(|leaf| {
+                let value = machine.wrap(leaf, superstate, quote!(state.clone()));
+                quote!(#machine_name::#leaf(state) => history.#field = Some(#value),)
+            });
+            quote! {
+                if !matches!(self, #(#inside)|*) {
+                    match previous {
+                        #(#arms)*
+                        _ => {}
+                    }
+                }
+            }
+        });
+
+        let tokens = quote! {
+            /// the last state of each superstate used as a history target
+            #(#attributes)*
+            #vis struct #history {
+                #(#fields),*
+            }
+
+            impl #history {
+                pub fn new() -> #history {
+                    #history {
+                        #(#empty),*
+                    }
+                }
+            }
+
+            impl Default for #history {
+                fn default() -> #history {
+                    #history::new()
+                }
+            }
+
+            impl #machine_name {
+                // records the superstates that `previous` was in and `self` is not
+                fn record_exit(&self, previous: &#machine_name, history: &mut #history) {
+                    #(#records)*
+                }
+            }
+        };
+
+        proc_macro::TokenStream::from(tokens)
+    }
+
+    // records in the history the superstates left by `next`, built from
+    // `self` by one of the transitions in `moves`
+    fn recorded(
+        &self,
+        moves: &[&Transition],
+        next: syn::export::TokenStream2,
+    ) -> syn::export::TokenStream2 {
+        if !moves.iter().any(|t| self.exits_history(t)) {
+            return next;
+        }
+
+        let state = if self.output().is_some() { quote!(next.0) } else { quote!(next) };
+        quote!({
+            let previous = self.clone();
+            let next = #next;
+            #state.record_exit(&previous, history);
+            next
+        })
+    }
+
+    // whether `t` can leave a superstate whose history is a target
+    fn exits_history(&self, t: &Transition) -> bool {
+        let machine = match &self.machine {
+            Some(machine) => machine,
+            None => return false,
+        };
+        let starts = self.start_states(t);
+
+        self.history_superstates().into_iter().any(|superstate| {
+            let inside = machine.leaves(superstate);
+            if !starts.iter().any(|start| inside.contains(&start)) {
+                return false;
+            }
+
+            if t.pop {
+                true
+            } else if let Some(history) = &t.history {
+                machine.leaves(&history.superstate).iter().any(|s| !inside.contains(s))
+            } else {
+                t.end.iter().any(|end| !inside.contains(&end))
+            }
+        })
+    }
+
+    // whether the methods firing `moves` read or update the history. The
+    // completion transitions can fire after any of them
+    fn needs_history(&self, moves: &[&Transition]) -> bool {
+        let uses_history = |t: &&Transition| t.history.is_some() || self.exits_history(t);
+        moves.iter().any(uses_history) || self.completions_need_history()
+    }
+
+    fn completions_need_history(&self) -> bool {
+        self.transitions
+            .iter()
+            .filter(|t| matches!(t.trigger, Trigger::Completion))
+            .any(|t| t.history.is_some() || self.exits_history(t))
+    }
+
+    fn all_transitions(&self) -> Vec<&Transition> {
+        self.transitions.iter().collect()
+    }
+
+    // the parameters of a dispatch over `messages`, with the history if
+    // one of their methods takes it
+    fn dispatch_params(
+        &self,
+        messages: &HashMap<&syn::Ident, Vec<&Transition>>,
+    ) -> syn::export::TokenStream2 {
+        let moves = messages.values().flatten().cloned().collect::<Vec<_>>();
+        self.entry_params(self.needs_history(&moves))
+    }
+
+    // the states a transition applies to: its start state, or the states of
+    // its start superstate that have no transition of their own for the trigger
+    fn start_states(&self, t: &Transition) -> Vec<Ident> {
+        let machine = match &self.machine {
+            Some(machine) => machine,
+            None => return vec![t.start.clone()],
+        };
+
+        machine
+            .leaves(&t.start)
+            .into_iter()
+            .filter(|leaf| {
+                let mut states = machine.ancestors(leaf);
+                states.push(leaf);
+                let nearest = states.into_iter().rev().find(|state| {
+                    self.transitions
+                        .iter()
+                        .any(|o| &o.start == *state && o.trigger.same(&t.trigger))
+                });
+                nearest == Some(&t.start)
+            })
+            .cloned()
+            .collect()
+    }
+
+    // the state handler's arguments after `self`
+    fn handler_args(&self, t: &Transition, span: Span) -> Vec<syn::export::TokenStream2> {
+        let mut args = Vec::new();
+        if self.context().is_some() {
+            args.push(quote_spanned!(span=> ctx));
+        }
+        if t.trigger.message().is_some() {
+            args.push(quote_spanned!(span=> input));
+            if self.options.runner {
+                args.push(quote_spanned!(span=> queue));
+            }
+        }
+        args
+    }
+
+    // the state handler's parameters after `self`
+    fn handler_params(&self, t: &Transition, span: Span) -> syn::export::TokenStream2 {
+        let ctx = self.context_param(span);
+        match t.trigger.message() {
+            Some(message) => {
+                let queue = self.queue_param(span);
+                quote_spanned!(span=> #ctx, input: #message #queue)
+            }
+            None => quote!(#ctx),
+        }
+    }
+
+    fn context(&self) -> Option<&Type> {
+        self.machine.as_ref().and_then(|machine| machine.context())
+    }
+
+    /// the names of the generated items, from the machine's options
+    pub fn naming(&self) -> Naming {
+        self.machine
+            .as_ref()
+            .map(|machine| machine.naming().clone())
+            .unwrap_or_default()
+    }
+
+    // the declared visibility of the machine, given to the generated types
+    fn vis(&self) -> syn::export::TokenStream2 {
+        match &self.machine {
+            Some(machine) => {
+                let vis = machine.vis();
+                quote!(#vis)
+            }
+            None => quote!(pub),
+        }
+    }
+
+    // the variant of the invalid state
+    fn machine_error(&self) -> Ident {
+        self.naming().error
+    }
+
+    // `, ctx: &mut Ctx` after `self` if the machine has a context
+    fn context_param(&self, span: Span) -> Option<syn::export::TokenStream2> {
+        self.context().map(|context| quote_spanned!(span=> , ctx: &mut #context))
+    }
+
+    /// name of the hidden macro through which `parallel!` and the tables
+    /// delegating to the machine get the messages accepted by its states
+    pub fn description_macro(machine_name: &Ident) -> Ident {
+        Ident::new(&format!("__transitions_{}", machine_name), machine_name.span())
+    }
+
+    /// the first child machine whose transitions are not described yet
+    pub fn undescribed_child(&self) -> Option<Ident> {
+        let machine = self.machine.as_ref()?;
+        self.transitions
+            .iter()
+            .filter_map(|t| {
+                let field = t.delegate.as_ref()?;
+                child_type(machine, &t.start, field)?.segments.last()
+            })
+            .map(|segment| segment.value().ident.clone())
+            .find(|child| !self.children.iter().any(|c| &c.name == child))
+    }
+
+    // the description of the child machine held in `field` of `state`
+    fn child(&self, state: &Ident, field: &Ident) -> Option<&Region> {
+        let child = child_type(self.machine.as_ref()?, state, field)?.segments.last()?;
+        let child = &child.value().ident;
+        self.children.iter().find(|c| &c.name == child)
+    }
+
+    // the generated macro calls back `parallel!` or `transitions!` with the
+    // machine's messages, the states accepting each of them, and the edges of
+    // the graph
+    fn generate_description(
+        &self,
+        messages: &HashMap<&syn::Ident, Vec<&Transition>>,
+    ) -> syn::export::TokenStream {
+        let machine_name = &self.machine_name;
+        let macro_name = Transitions::description_macro(machine_name);
+        let macro_name = Ident::new(&macro_name.to_string(), Span::call_site());
+
+        // whether the `on_*` methods only take the message and return the machine
+        let plain = self.context().is_none()
+            && !self.options.runner
+            && !self.transitions.iter().any(|t| t.pop)
+            && self.output().is_none()
+            && !self.needs_history(&self.all_transitions());
+        let kind = Ident::new(if plain { "plain" } else { "extended" }, Span::call_site());
+        let finals = if self.options.final_states { "final_states" } else { "no_final_states" };
+        let finals = Ident::new(finals, Span::call_site());
+        let prefix = self.naming().handler_prefix;
+
+        let mut names = messages.keys().collect::<Vec<_>>();
+        names.sort_by_key(|message| message.to_string());
+
+        let mut accepts = Vec::new();
+        let mut edges = Vec::new();
+        for t in self.transitions.iter().filter(|t| !t.defer) {
+            for start in self.start_states(t) {
+                if let Some(message) = t.trigger.message() {
+                    accepts.push(quote!((#message, #machine_name::#start(_))));
+                }
+                for end in t.end.iter() {
+                    let label = t.trigger.to_string();
+                    edges.push(quote!((#start, #label, #end)));
+                }
+            }
+        }
+
+        let tokens = quote! {
+            #[allow(unused_macros)]
+            #[doc(hidden)]
+            macro_rules! #macro_name {
+                (:: $krate:ident :: $callback:ident ! { $($args:tt)* }) => {
+                    :: $krate :: $callback! {
+                        { #machine_name #kind #finals #prefix [#(#names),*] [#(#accepts),*] [#(#edges),*] }
+                        $($args)*
+                    }
+                };
+            }
+        };
+
+        proc_macro::TokenStream::from(tokens)
+    }
+
+    // the history owned by the timer and the runner: its field, its
+    // initialization, and the binding passing it to the machine
+    fn owned_history(
+        &self,
+        history: bool,
+    ) -> (
+        Option<syn::export::TokenStream2>,
+        Option<syn::export::TokenStream2>,
+        Option<syn::export::TokenStream2>,
+    ) {
+        if !history {
+            return (None, None, None);
+        }
+
+        let history = self.history_struct();
+        (
+            Some(quote!(history: #history,)),
+            Some(quote!(history: #history::new(),)),
+            Some(quote!(let history = &mut self.history;)),
+        )
+    }
+
+    // the parameters of the generated methods after `self`: the context,
+    // and the history if their transitions use it
+    fn entry_params(&self, history: bool) -> syn::export::TokenStream2 {
+        let ctx = self.context_param(Span::call_site());
+        let history = if !history {
+            None
+        } else {
+            let history = self.history_struct();
+            Some(quote!(, history: &mut #history))
+        };
+        quote!(#ctx #history)
+    }
+
+    // `ctx` and `history` passed along to the generated methods
+    fn entry_args(&self, history: bool) -> Vec<syn::export::TokenStream2> {
+        let mut args = Vec::new();
+        if self.context().is_some() {
+            args.push(quote!(ctx));
+        }
+        if history {
+            args.push(quote!(history));
+        }
+        args
+    }
+
+    fn queue_param(&self, span: Span) -> Option<syn::export::TokenStream2> {
+        if self.options.runner {
+            let queue = Ident::new(&format!("{}Queue", self.machine_name), Span::call_site());
+            Some(quote_spanned!(span=> , queue: &mut #queue))
+        } else {
+            None
+        }
+    }
+
+    // the outputs of the transitions: the declared output type, or the effects enum
+    fn output(&self) -> Option<Type> {
+        if self.options.effects.is_some() {
+            let effects = self.effects_enum();
+            Some(parse_quote!(#effects))
+        } else {
+            self.options.output.clone()
+        }
+    }
+
+    fn effects_enum(&self) -> Ident {
+        Ident::new(&format!("{}Effects", self.machine_name), Span::call_site())
+    }
+
+    // what the machine's methods return: the next state, and the outputs if any
+    fn machine_output(&self) -> syn::export::TokenStream2 {
+        let machine_name = &self.machine_name;
+        match self.output() {
+            Some(output) => quote!((#machine_name, Vec<#output>)),
+            None => quote!(#machine_name),
+        }
+    }
+
+    // what a state handler returns
+    fn handler_output(&self, t: &Transition) -> syn::export::TokenStream2 {
+        let end = t.handler_end(self.machine.as_ref(), &self.machine_name);
+        match self.output() {
+            Some(output) => quote!((#end, Vec<#output>)),
+            None => end,
+        }
+    }
+
+    // `state` with no outputs
+    fn without_output(&self, state: syn::export::TokenStream2) -> syn::export::TokenStream2 {
+        if self.output().is_some() {
+            quote!((#state, Vec::new()))
+        } else {
+            state
+        }
+    }
+
+    fn generate_impl(
+        &self,
+        messages: &HashMap<&syn::Ident, Vec<&Transition>>,
+    ) -> syn::export::TokenStream {
+        let machine_name = &self.machine_name;
+
+        // with a runner, the handlers post messages and defer others, so the
+        // messages go through `dispatch` or the runner's own methods
+        if self.options.runner {
+            return proc_macro::TokenStream::new();
+        }
+
+        let functions = messages
+            .iter()
+            .map(|(message, moves)| self.generate_fn(message, moves.as_slice()))
+            .collect::<Vec<_>>();
+
+        let tokens = quote! {
+          impl #machine_name {
+            #(#functions)*
+          }
+        };
+
+        proc_macro::TokenStream::from(tokens)
+    }
+
+    // one trait per start state, listing the handlers it must implement
+    fn generate_traits(&self) -> syn::export::TokenStream {
+        let vis = self.vis();
+        let traits = self.by_state().into_iter().map(|(state, transitions)| {
+            let trait_name = handlers_trait_name(&self.machine_name, state);
+
+            let methods = transitions.iter().map(|t| {
+                let fn_ident = t.trigger.handler_name(&self.naming());
+                let params = self.handler_params(t, t.span);
+                let end = self.handler_output(t);
+
+                // a default body for the handlers building a state without members
+                match self.unit_body(t) {
+                    Some(body) => quote_spanned! {t.span=>
+                        #[allow(unused_variables)]
+                        fn #fn_ident(self #params) -> #end where Self: Sized {
+                            #body
+                        }
+                    },
+                    None => quote_spanned! {t.span=>
+                        fn #fn_ident(self #params) -> #end;
+                    },
+                }
+            });
+
+            quote! {
+                #vis trait #trait_name {
+                    #(#methods)*
+                }
+            }
+        });
+
+        proc_macro::TokenStream::from(quote!(#(#traits)*))
+    }
+
+    // deadlines and clocks for the `after(...)` transitions
+    fn generate_timers(&self) -> syn::export::TokenStream {
+        let vis = self.vis();
+        let machine_name = &self.machine_name;
+        let error_variant = self.machine_error();
+        let clock = Ident::new(&format!("{}Clock", machine_name), Span::call_site());
+        let system_clock = Ident::new(&format!("{}SystemClock", machine_name), Span::call_site());
+        let manual_clock = Ident::new(&format!("{}ManualClock", machine_name), Span::call_site());
+        let timer = Ident::new(&format!("{}Timer", machine_name), Span::call_site());
+
+        let timed = self
+            .transitions
+            .iter()
+            .filter_map(|t| match &t.trigger {
+                Trigger::After(delay) => Some((t, delay)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let deadlines = timed.iter().flat_map(|(t, delay)| {
+            self.start_states(t).into_iter().map(move |start| {
+                quote! {
+                    #machine_name::#start(_) => Some(entered_at + (#delay)),
+                }
+            })
+        });
+
+        let timed_moves = timed.iter().map(|(t, _)| *t).collect::<Vec<_>>();
+        let history = self.needs_history(&timed_moves);
+        let moves = timed.iter().map(|(t, _)| self.generate_move(t));
+        let unchanged = self.without_output(quote!(state));
+        let timeout = self.completed(self.recorded(&timed_moves, quote! {
+            match self {
+                #(#moves)*
+                state => #unchanged,
+            }
+        }));
+        let ret = self.machine_output();
+        let unchanged = self.without_output(quote!(self));
+        let params = self.entry_params(history);
+        let args = self.entry_args(history);
+
+        let mut stream = proc_macro::TokenStream::new();
+
+        let tokens = quote! {
+            impl #machine_name {
+                /// when the current state's timed transition will fire, if it has one
+                pub fn next_deadline(&self, entered_at: ::std::time::Instant) -> Option<::std::time::Instant> {
+                    match self {
+                        #(#deadlines)*
+                        _ => None,
+                    }
+                }
+
+                /// fires the current state's timed transition if its deadline has passed
+                pub fn on_tick(self #params, entered_at: ::std::time::Instant, now: ::std::time::Instant) -> #ret {
+                    match self.next_deadline(entered_at) {
+                        Some(deadline) if now >= deadline => self.__fire_timer(#(#args),*),
+                        _ => #unchanged,
+                    }
+                }
+
+                // not named after the handlers, which could clash with a message's
+                fn __fire_timer(self #params) -> #ret {
+                    #timeout
+                }
+            }
+        };
+        stream.extend(proc_macro::TokenStream::from(tokens));
+
+        let tokens = quote! {
+            #vis trait #clock {
+                fn now(&self) -> ::std::time::Instant;
+            }
+
+            impl<'a, C: #clock> #clock for &'a C {
+                fn now(&self) -> ::std::time::Instant {
+                    (*self).now()
+                }
+            }
+
+            #[derive(Clone, Copy, Debug, Default)]
+            #vis struct #system_clock;
+
+            impl #clock for #system_clock {
+                fn now(&self) -> ::std::time::Instant {
+                    ::std::time::Instant::now()
+                }
+            }
+
+            /// clock that only moves when `advance` is called
+            #[derive(Clone, Debug)]
+            #vis struct #manual_clock {
+                now: ::std::cell::Cell<::std::time::Instant>,
+            }
+
+            impl #manual_clock {
+                pub fn new() -> #manual_clock {
+                    #manual_clock {
+                        now: ::std::cell::Cell::new(::std::time::Instant::now()),
+                    }
+                }
+
+                pub fn advance(&self, duration: ::std::time::Duration) {
+                    self.now.set(self.now.get() + duration);
+                }
+            }
+
+            impl Default for #manual_clock {
+                fn default() -> #manual_clock {
+                    #manual_clock::new()
+                }
+            }
+
+            impl #clock for #manual_clock {
+                fn now(&self) -> ::std::time::Instant {
+                    self.now.get()
+                }
+            }
+        };
+        stream.extend(proc_macro::TokenStream::from(tokens));
+
+        let ctx_param = self.context_param(Span::call_site());
+        let ctx = self.entry_args(history);
+        let (history_field, history_init, history) = self.owned_history(history);
+
+        // with outputs, `tick` and `update` return the outputs of the transition
+        let (output_ret, timeout, no_output, transition, updated) = match self.output() {
+            Some(output) => (
+                quote!(-> Vec<#output>),
+                quote! {
+                    #history
+                    let (state, output) = state.__fire_timer(#(#ctx),*);
+                    self.state = state;
+                    self.entered_at = now;
+                    output
+                },
+                quote!(Vec::new()),
+                quote!((#machine_name, Vec<#output>)),
+                quote! {
+                    let (state, output) = transition(state);
+                    self.state = state;
+                },
+            ),
+            None => (
+                quote!(),
+                quote! {
+                    #history
+                    self.state = state.__fire_timer(#(#ctx),*);
+                    self.entered_at = now;
+                },
+                quote!({}),
+                quote!(#machine_name),
+                quote! {
+                    self.state = transition(state);
+                },
+            ),
+        };
+        let output = self.output().map(|_| quote!(output));
+
+        let tokens = quote! {
+            /// holds the machine along with the time it entered its current state
+            #[derive(Debug)]
+            #vis struct #timer<C: #clock> {
+                state: #machine_name,
+                entered_at: ::std::time::Instant,
+                clock: C,
+                #history_field
+            }
+
+            impl<C: #clock> #timer<C> {
+                pub fn new(state: #machine_name, clock: C) -> #timer<C> {
+                    let entered_at = clock.now();
+                    #timer { state, entered_at, clock, #history_init }
+                }
+
+                pub fn state(&self) -> &#machine_name {
+                    &self.state
+                }
+
+                pub fn into_state(self) -> #machine_name {
+                    self.state
+                }
+
+                pub fn next_deadline(&self) -> Option<::std::time::Instant> {
+                    self.state.next_deadline(self.entered_at)
+                }
+
+                /// fires the timed transition if its deadline has passed
+                pub fn tick(&mut self #ctx_param) #output_ret {
+                    let now = self.clock.now();
+                    match self.next_deadline() {
+                        Some(deadline) if now >= deadline => {
+                            let state = ::std::mem::replace(&mut self.state, #machine_name::#error_variant);
+                            #timeout
+                        }
+                        _ => #no_output,
+                    }
+                }
+
+                /// applies a message transition, restarting the timer if the state changed
+                pub fn update<F: FnOnce(#machine_name) -> #transition>(&mut self, transition: F) #output_ret {
+                    let state = ::std::mem::replace(&mut self.state, #machine_name::#error_variant);
+                    let previous = ::std::mem::discriminant(&state);
+                    #updated
+                    if ::std::mem::discriminant(&self.state) != previous {
+                        self.entered_at = self.clock.now();
+                    }
+                    #output
+                }
+            }
+        };
+        stream.extend(proc_macro::TokenStream::from(tokens));
+
+        stream
+    }
+
+    fn has_completions(&self) -> bool {
+        self.transitions.iter().any(|t| matches!(t.trigger, Trigger::Completion))
+    }
+
+    // settles the state reached by `next` through the completion transitions
+    fn completed(&self, next: syn::export::TokenStream2) -> syn::export::TokenStream2 {
+        let args = self.entry_args(self.completions_need_history());
+        let ctx = quote!(#(#args),*);
+        if !self.has_completions() {
+            next
+        } else if self.output().is_some() {
+            quote!({
+                let (state, mut output) = #next;
+                let (state, more) = state.complete(#ctx);
+                output.extend(more);
+                (state, output)
+            })
+        } else {
+            quote!((#next).complete(#ctx))
+        }
+    }
+
+    fn generate_completions(&self) -> syn::export::TokenStream {
+        let machine_name = &self.machine_name;
+        let error_variant = self.machine_error();
+        let max_steps = match &self.options.max_steps {
+            Some(max_steps) => quote!(#max_steps),
+            None => quote!(32),
+        };
+
+        let completions = self
+            .transitions
+            .iter()
+            .filter(|t| matches!(t.trigger, Trigger::Completion))
+            .collect::<Vec<_>>();
+
+        let moves = completions.iter().map(|t| self.generate_move(t));
+        let transient = completions.iter().flat_map(|t| self.start_states(t)).map(|start| {
+            quote!(#machine_name::#start(_) => #machine_name::#error_variant,)
+        });
+
+        let body = if self.output().is_some() {
+            quote! {
+                let mut state = self;
+                let mut output = Vec::new();
+                for _ in 0..#max_steps {
+                    let (next, more) = match state {
+                        #(#moves)*
+                        state => return (state, output),
+                    };
+                    state = next;
+                    output.extend(more);
+                }
+
+                let state = match state {
+                    #(#transient)*
+                    state => state,
+                };
+                (state, output)
+            }
+        } else {
+            quote! {
+                let mut state = self;
+                for _ in 0..#max_steps {
+                    state = match state {
+                        #(#moves)*
+                        state => return state,
+                    };
+                }
+
+                match state {
+                    #(#transient)*
+                    state => state,
+                }
+            }
+        };
+        let ret = self.machine_output();
+        let ctx = self.entry_params(self.completions_need_history());
+
+        let tokens = quote! {
+            impl #machine_name {
+                /// fires the completion transitions until the machine reaches a
+                /// stable state, or `Error` if it is still moving after the step limit
+                pub fn complete(self #ctx) -> #ret {
+                    #body
+                }
+            }
+        };
+
+        proc_macro::TokenStream::from(tokens)
+    }
+
+    // effects enum, and the trait performing them
+    fn generate_effects(&self, effects: &[Ident]) -> syn::export::TokenStream {
+        let vis = self.vis();
+        let effects_enum = self.effects_enum();
+        let executor = Ident::new(&format!("{}EffectExecutor", self.machine_name), Span::call_site());
+
+        let variants = effects.iter().map(|effect| quote!(#effect(#effect)));
+
+        let conversions = effects.iter().map(|effect| {
+            quote! {
+                impl From<#effect> for #effects_enum {
+                    fn from(effect: #effect) -> #effects_enum {
+                        #effects_enum::#effect(effect)
+                    }
+                }
+            }
+        });
+
+        let methods = effects.iter().map(|effect| {
+            let fn_ident = effect_name(effect);
+            quote! {
+                fn #fn_ident(&mut self, effect: #effect);
+            }
+        });
+
+        let arms = effects.iter().map(|effect| {
+            let fn_ident = effect_name(effect);
+            quote! {
+                #effects_enum::#effect(effect) => self.#fn_ident(effect),
+            }
+        });
+
+        // the effects are returned along with the machine, so they derive the same traits
+        let derives = match &self.machine {
+            Some(machine) => {
+                let derives = machine.attributes().into_iter().filter(|a| a.path.is_ident("derive"));
+                quote!(#(#derives)*)
+            }
+            None => quote!(#[derive(Clone,Debug,PartialEq)]),
+        };
+
+        let tokens = quote! {
+            #derives
+            #vis enum #effects_enum {
+                #(#variants),*
+            }
+
+            #(#conversions)*
+
+            /// performs the effects returned by the transitions
+            #vis trait #executor {
+                #(#methods)*
+
+                fn execute(&mut self, effect: #effects_enum) {
+                    match effect {
+                        #(#arms)*
+                    }
+                }
+
+                fn execute_all<I: IntoIterator<Item = #effects_enum>>(&mut self, effects: I) {
+                    for effect in effects {
+                        self.execute(effect);
+                    }
+                }
+            }
+        };
+
+        proc_macro::TokenStream::from(tokens)
+    }
+
+    // message queue and runner, with the deferred messages
+    fn generate_runner(
+        &self,
+        messages: &HashMap<&syn::Ident, Vec<&Transition>>,
+    ) -> syn::export::TokenStream {
+        let vis = self.vis();
+        let machine_name = &self.machine_name;
+        let error_variant = self.machine_error();
+        let message_enum = self.messages_enum();
+        let queue = Ident::new(&format!("{}Queue", machine_name), Span::call_site());
+        let runner = Ident::new(&format!("{}Runner", machine_name), Span::call_site());
+
+        let mut stream = proc_macro::TokenStream::new();
+
+        let history = self.needs_history(&self.all_transitions());
+        let ctx_param = self.context_param(Span::call_site());
+        let ctx = self.entry_args(history);
+        let ctx = quote!(#(#ctx,)*);
+        let (history_field, history_init, history) = self.owned_history(history);
+
+        let dispatch = messages.iter().map(|(message, moves)| {
+            let next = self.generate_next(moves);
+            quote! {
+                #message_enum::#message(input) => #next,
+            }
+        });
+        let (groups, group_conversions) = self.included_groups();
+
+        // the message is handled before the queued ones, and the messages
+        // posted by the handlers go to the runner's queue
+        let ctx_arg = self.context().map(|_| quote!(ctx,));
+        let handlers = messages.keys().map(|message| {
+            let fn_ident = self.naming().handler(message);
+            quote! {
+                pub fn #fn_ident(&mut self #ctx_param, input: #message) {
+                    self.handle(#ctx_arg #message_enum::#message(input));
+                }
+            }
+        });
+
+        let deferred = self.transitions.iter().filter(|t| t.defer).flat_map(|t| {
+            let message = t.trigger.message();
+            let message_enum = &message_enum;
+            self.start_states(t).into_iter().map(move |start| {
+                quote! {
+                    (#machine_name::#start(_), #message_enum::#message(_)) => true,
+                }
+            })
+        });
+
+        let ret = self.machine_output();
+        let dispatch_params = self.entry_params(self.needs_history(&self.all_transitions()));
+
+        // with outputs, the runner collects them until they are taken
+        let (outputs_field, outputs_init, outputs_take, dispatched) = match self.output() {
+            Some(output) => (
+                quote!(outputs: Vec<#output>,),
+                quote!(outputs: Vec::new(),),
+                quote! {
+                    /// the outputs of the transitions run so far
+                    pub fn take_outputs(&mut self) -> Vec<#output> {
+                        ::std::mem::replace(&mut self.outputs, Vec::new())
+                    }
+                },
+                quote! {
+                    #history
+                    let (state, output) = state.dispatch(#ctx message, &mut self.queue);
+                    self.state = state;
+                    self.outputs.extend(output);
+                },
+            ),
+            None => (
+                quote!(),
+                quote!(),
+                quote!(),
+                quote! {
+                    #history
+                    self.state = state.dispatch(#ctx message, &mut self.queue);
+                },
+            ),
+        };
+        let kept = self.without_output(quote!(self));
+        let next_state = match self.output() {
+            Some(_) => quote!(&next.0),
+            None => quote!(&next),
+        };
+
+        let conversions = messages.keys().map(|message| {
+            quote! {
+                impl From<#message> for #message_enum {
+                    fn from(message: #message) -> #message_enum {
+                        #message_enum::#message(message)
+                    }
+                }
+            }
+        });
+
+        let tokens = quote! {
+            impl #machine_name {
+                /// a message deferred by the current state is kept in the queue,
+                /// and dispatched again first once the state changes
+                #[allow(unused_variables)]
+                pub fn dispatch(self #dispatch_params, message: #message_enum, queue: &mut #queue) -> #ret {
+                    if self.defers(&message) {
+                        queue.deferred.push_back(message);
+                        return #kept;
+                    }
+
+                    let previous = ::std::mem::discriminant(&self);
+                    let next = match message {
+                        #(#dispatch)*
+                        #(#groups)*
+                    };
+
+                    if ::std::mem::discriminant(#next_state) != previous {
+                        while let Some(message) = queue.deferred.pop_back() {
+                            queue.messages.push_front(message);
+                        }
+                    }
+                    next
+                }
+
+                /// whether the current state keeps `message` for a later state
+                pub fn defers(&self, message: &#message_enum) -> bool {
+                    match (self, message) {
+                        #(#deferred)*
+                        _ => false,
+                    }
+                }
+            }
+
+            #(#conversions)*
+            #(#group_conversions)*
+        };
+        stream.extend(proc_macro::TokenStream::from(tokens));
+
+        let tokens = quote! {
+            /// messages posted by the handlers, processed after the current one
+            #[derive(Clone, Debug, Default, PartialEq)]
+            #vis struct #queue {
+                messages: ::std::collections::VecDeque<#message_enum>,
+                deferred: ::std::collections::VecDeque<#message_enum>,
+            }
+
+            impl #queue {
+                pub fn post<M: Into<#message_enum>>(&mut self, message: M) {
+                    self.messages.push_back(message.into());
+                }
+
+                pub fn is_empty(&self) -> bool {
+                    self.messages.is_empty()
+                }
+
+                /// messages waiting for a state that accepts them
+                pub fn deferred(&self) -> &::std::collections::VecDeque<#message_enum> {
+                    &self.deferred
+                }
+            }
+
+            /// owns the machine and dispatches queued messages to it
+            #vis struct #runner {
+                state: #machine_name,
+                queue: #queue,
+                #outputs_field
+                #history_field
+            }
+
+            impl #runner {
+                pub fn new(state: #machine_name) -> #runner {
+                    #runner {
+                        state,
+                        queue: #queue::default(),
+                        #outputs_init
+                        #history_init
+                    }
+                }
+
+                pub fn state(&self) -> &#machine_name {
+                    &self.state
+                }
+
+                pub fn into_state(self) -> #machine_name {
+                    self.state
+                }
+
+                pub fn post<M: Into<#message_enum>>(&mut self, message: M) {
+                    self.queue.post(message);
+                }
+
+                /// messages waiting for a state that accepts them
+                pub fn deferred(&self) -> &::std::collections::VecDeque<#message_enum> {
+                    self.queue.deferred()
+                }
+
+                #outputs_take
+
+                /// dispatches messages until the queue is empty. When the state
+                /// changes, the deferred messages are dispatched again first
+                pub fn run_until_idle(&mut self #ctx_param) {
+                    while let Some(message) = self.queue.messages.pop_front() {
+                        self.handle(#ctx_arg message);
+                    }
+                }
+
+                #(#handlers)*
+
+                fn handle(&mut self #ctx_param, message: #message_enum) {
+                    let state = ::std::mem::replace(&mut self.state, #machine_name::#error_variant);
+                    #dispatched
+                }
+            }
+        };
+        stream.extend(proc_macro::TokenStream::from(tokens));
+
+        stream
+    }
+
+    // the typestate wrapper, with a method for each transition whose end
+    // state is known when the start state is
+    fn generate_typed(&self) -> syn::export::TokenStream {
+        let vis = self.vis();
+        let machine_name = &self.machine_name;
+        let typed = Ident::new(&format!("{}Typed", machine_name), Span::call_site());
+        let typed_state = Ident::new(&format!("{}TypedState", machine_name), Span::call_site());
+        let sealed = Ident::new(
+            &format!("__{}_typed", snake_case(&machine_name.to_string())),
+            Span::call_site(),
+        );
+        let machine = match &self.machine {
+            Some(machine) => machine,
+            None => return proc_macro::TokenStream::new(),
+        };
+
+        // the completion transitions could move the machine out of these states
+        let mut completing = Vec::new();
+        for t in self.transitions.iter() {
+            if let Trigger::Completion = t.trigger {
+                completing.extend(self.start_states(t));
+            }
+        }
+
+        let conversions = machine.states().map(|state| {
+            let path = machine.path(state);
+            quote! {
+                impl #sealed::Sealed for #path {}
+                impl #typed_state for #path {}
+
+                impl From<#typed<#path>> for #machine_name {
+                    fn from(typed: #typed<#path>) -> #machine_name {
+                        #machine_name::#state(typed.state)
+                    }
+                }
+            }
+        });
+
+        let methods = self
+            .transitions
+            .iter()
+            .filter(|t| {
+                t.trigger.message().is_some()
+                    && t.end.len() == 1
+                    && t.outcome.is_none()
+                    && !t.pop
+                    && !completing.contains(&t.end[0])
+            })
+            .flat_map(|t| self.start_states(t).into_iter().map(move |leaf| (t, leaf)))
+            .map(|(t, leaf)| {
+                let message = t.trigger.message().unwrap();
+                let fn_ident = Ident::new(&snake_case(&message.to_string()), Span::call_site());
+                let end = machine.path(&t.end[0]);
+                let leaf_path = machine.path(&leaf);
+                let ctx = self.context_param(Span::call_site());
+                let queue = self.queue_param(Span::call_site());
+                let call = self.handler_call(t, &leaf);
+
+                let (ret, next) = match self.output() {
+                    Some(output) => (
+                        quote!((#typed<#end>, Vec<#output>)),
+                        quote! {
+                            let (state, output) = #call;
+                            (#typed { state }, output)
+                        },
+                    ),
+                    None => (quote!(#typed<#end>), quote!(#typed { state: #call })),
+                };
+
+                quote! {
+                    impl #typed<#leaf_path> {
+                        pub fn #fn_ident(self #ctx, input: #message #queue) -> #ret {
+                            let state = self.state;
+                            #next
+                        }
+                    }
+                }
+            });
+
+        let tokens = quote! {
+            #[doc(hidden)]
+            mod #sealed {
+                pub trait Sealed {}
+            }
+
+            /// the states of the machine, which the typed wrapper can hold
+            #vis trait #typed_state: #sealed::Sealed {}
+
+            /// a state of the machine known at compile time, with a method for
+            /// each transition leaving it
+            #[derive(Clone, Debug, PartialEq)]
+            #vis struct #typed<S> {
+                state: S,
+            }
+
+            impl<S: #typed_state> #typed<S> {
+                pub fn new(state: S) -> #typed<S> {
+                    #typed { state }
+                }
+
+                pub fn state(&self) -> &S {
+                    &self.state
+                }
+
+                pub fn into_state(self) -> S {
+                    self.state
+                }
+            }
+
+            #(#conversions)*
+
+            #(#methods)*
+        };
+
+        proc_macro::TokenStream::from(tokens)
+    }
+
+    /// `TrafficMessages`, or `TrafficPedestrianMessages` for the `Pedestrian` group
+    fn messages_enum(&self) -> Ident {
+        match &self.options.group {
+            Some(group) => self.group_enum(group),
+            None => self.naming().messages(&self.machine_name),
+        }
+    }
+
+    fn group_enum(&self, group: &Ident) -> Ident {
+        Ident::new(
+            &format!("{}{}Messages", self.machine_name, group),
+            Span::call_site(),
+        )
+    }
+
+    // the conversions of a group table's messages, its dispatch, and whether
+    // it has a transition for the current state, used by `is_final()`
+    fn generate_group(
+        &self,
+        group: &Ident,
+        messages: &HashMap<&syn::Ident, Vec<&Transition>>,
+    ) -> syn::export::TokenStream {
+        let machine_name = &self.machine_name;
+        let message_enum = self.messages_enum();
+        let group = snake_case(&group.to_string());
+        let dispatch_ident = Ident::new(&format!("dispatch_{}", group), Span::call_site());
+        let leaves_ident = Ident::new(&format!("__leaves_{}", group), Span::call_site());
+        let params = self.dispatch_params(messages);
+        let ret = self.machine_output();
+
+        let conversions = messages.keys().map(|message| {
+            quote! {
+                impl From<#message> for #message_enum {
+                    fn from(message: #message) -> #message_enum {
+                        #message_enum::#message(message)
+                    }
+                }
+            }
+        });
+
+        let dispatch = messages.iter().map(|(message, moves)| {
+            let fn_ident = self.naming().handler(message);
+            let args = self.entry_args(self.needs_history(moves));
+            quote! {
+                #message_enum::#message(input) => self.#fn_ident(#(#args,)* input),
+            }
+        });
+
+        let started = self
+            .transitions
+            .iter()
+            .flat_map(|t| self.start_states(t))
+            .map(|state| quote!(#machine_name::#state(_)))
+            .collect::<Vec<_>>();
+        let leaves = if started.is_empty() {
+            quote!(false)
+        } else {
+            quote!(matches!(self, #(#started)|*))
+        };
+
+        let tokens = quote! {
+            #(#conversions)*
+
+            impl #machine_name {
+                pub fn #dispatch_ident(self #params, message: #message_enum) -> #ret {
+                    match message {
+                        #(#dispatch)*
+                    }
+                }
+
+                #[doc(hidden)]
+                pub fn #leaves_ident(&self) -> bool {
+                    #leaves
+                }
+            }
+        };
+
+        proc_macro::TokenStream::from(tokens)
+    }
+
+    // the dispatch of the main messages enum, including the groups' messages
+    fn generate_dispatch(
+        &self,
+        messages: &HashMap<&syn::Ident, Vec<&Transition>>,
+    ) -> syn::export::TokenStream {
+        let machine_name = &self.machine_name;
+        let message_enum = self.messages_enum();
+        let params = self.dispatch_params(messages);
+        let ret = self.machine_output();
+
+        let dispatch = messages.iter().map(|(message, moves)| {
+            let fn_ident = self.naming().handler(message);
+            let args = self.entry_args(self.needs_history(moves));
+            quote! {
+                #message_enum::#message(input) => self.#fn_ident(#(#args,)* input),
+            }
+        });
+        let (groups, conversions) = self.included_groups();
+
+        let tokens = quote! {
+            impl #machine_name {
+                pub fn dispatch(self #params, message: #message_enum) -> #ret {
+                    match message {
+                        #(#dispatch)*
+                        #(#groups)*
+                    }
+                }
+            }
+
+            #(#conversions)*
+        };
+
+        proc_macro::TokenStream::from(tokens)
+    }
+
+    // the dispatch arms of the groups included in the main messages enum,
+    // and their conversions into it
+    fn included_groups(
+        &self,
+    ) -> (Vec<syn::export::TokenStream2>, Vec<syn::export::TokenStream2>) {
+        let message_enum = self.messages_enum();
+        let ctx = self.context().map(|_| quote!(ctx,));
+
+        let arms = self
+            .options
+            .groups
+            .iter()
+            .map(|group| {
+                let dispatch_ident = Ident::new(
+                    &format!("dispatch_{}", snake_case(&group.to_string())),
+                    Span::call_site(),
+                );
+                quote! {
+                    #message_enum::#group(message) => self.#dispatch_ident(#ctx message),
+                }
+            })
+            .collect();
+
+        let conversions = self
+            .options
+            .groups
+            .iter()
+            .map(|group| {
+                let group_enum = self.group_enum(group);
+                quote! {
+                    impl From<#group_enum> for #message_enum {
+                        fn from(message: #group_enum) -> #message_enum {
+                            #message_enum::#group(message)
+                        }
+                    }
+                }
+            })
+            .collect();
+
+        (arms, conversions)
+    }
+
+    fn stack_name(&self) -> Ident {
+        Ident::new(&format!("{}Stack", self.machine_name), Span::call_site())
+    }
+
+    // the stack of states, whose top receives the messages
+    fn generate_stack(
+        &self,
+        messages: &HashMap<&syn::Ident, Vec<&Transition>>,
+    ) -> syn::export::TokenStream {
+        let vis = self.vis();
+        let machine_name = &self.machine_name;
+        let error_variant = self.machine_error();
+        let stack_name = self.stack_name();
+        let message_enum = self.messages_enum();
+        let dispatch_params = self.dispatch_params(messages);
+
+        let mut names = messages.keys().collect::<Vec<_>>();
+        names.sort_by_key(|message| message.to_string());
+
+        let methods = names.iter().map(|message| {
+            let fn_ident = self.naming().handler(message);
+            let transitions = &messages[*message];
+            let history = self.needs_history(transitions);
+            let params = self.entry_params(history);
+            let args = &self.entry_args(history);
+
+            let pops = transitions
+                .iter()
+                .filter(|t| t.pop)
+                .flat_map(|t| self.start_states(t))
+                .map(|leaf| quote!(#machine_name::#leaf(_)))
+                .collect::<Vec<_>>();
+            let pushes = transitions
+                .iter()
+                .filter(|t| t.push)
+                .flat_map(|t| self.start_states(t))
+                .map(|leaf| quote!(#machine_name::#leaf(_)))
+                .collect::<Vec<_>>();
+
+            let pop_arm = if pops.is_empty() {
+                None
+            } else {
+                Some(quote! {
+                    #(#pops)|* => {
+                        if self.states.is_empty() {
+                            self.states.push(#machine_name::#error_variant);
+                        }
+                    }
+                })
+            };
+            let push_arm = if pushes.is_empty() {
+                None
+            } else {
+                Some(quote! {
+                    #(#pushes)|* => {
+                        self.states.push(state.clone());
+                        let next = state.#fn_ident(#(#args,)* input);
+                        self.states.push(next);
+                    }
+                })
+            };
+
+            quote! {
+                pub fn #fn_ident(&mut self #params, input: #message) {
+                    let state = self.states.pop().unwrap_or(#machine_name::#error_variant);
+                    match state {
+                        #pop_arm
+                        #push_arm
+                        state => {
+                            let next = state.#fn_ident(#(#args,)* input);
+                            self.states.push(next);
+                        }
+                    }
+                }
+            }
+        });
+
+        let dispatch = names.iter().map(|message| {
+            let fn_ident = self.naming().handler(message);
+            let args = self.entry_args(self.needs_history(&messages[*message]));
+            quote! {
+                #message_enum::#message(input) => self.#fn_ident(#(#args,)* input),
+            }
+        });
+
+        let tokens = quote! {
+            /// the states of the machine, the suspended ones below the current one
+            #vis struct #stack_name {
+                states: Vec<#machine_name>,
+            }
+
+            impl #stack_name {
+                pub fn new(state: #machine_name) -> #stack_name {
+                    #stack_name { states: vec![state] }
+                }
+
+                /// the current state
+                pub fn top(&self) -> &#machine_name {
+                    self.states.last().unwrap()
+                }
+
+                /// all the states, from the bottom of the stack to the top
+                pub fn states(&self) -> &[#machine_name] {
+                    &self.states
+                }
+
+                pub fn depth(&self) -> usize {
+                    self.states.len()
+                }
+
+                #(#methods)*
+
+                pub fn dispatch(&mut self #dispatch_params, message: #message_enum) {
+                    match message {
+                        #(#dispatch)*
+                    }
+                }
+            }
+        };
+
+        proc_macro::TokenStream::from(tokens)
+    }
+
+    // the body of a handler without one in the table that builds a state
+    // without members, which the user does not have to write
+    fn unit_body(&self, t: &Transition) -> Option<syn::export::TokenStream2> {
+        let machine = self.machine.as_ref()?;
+        if t.body.is_some() || t.outcome.is_some() || t.end.len() != 1 {
+            return None;
+        }
+
+        let end = &t.end[0];
+        if machine.fields(end)?.iter().next().is_some() {
+            return None;
+        }
+
+        let path = state_path(Some(machine), end);
+        Some(self.without_output(quote!(#path {})))
+    }
+
+    // the handlers inferred by `unit_body`, in a trait so that the methods
+    // written by the user, which are inherent, take precedence
+    fn generate_unit_handlers(
+        &self,
+        state: &Ident,
+        transitions: &[&Transition],
+    ) -> syn::export::TokenStream {
+        let mut signatures = Vec::new();
+        let mut methods = Vec::new();
+        for t in transitions {
+            let body = match self.unit_body(t) {
+                Some(body) => body,
+                None => continue,
+            };
+            let fn_ident = t.trigger.handler_name(&self.naming());
+            let params = self.handler_params(t, t.span);
+            let end = self.handler_output(t);
+
+            signatures.push(quote_spanned! {t.span=>
+                fn #fn_ident(self #params) -> #end;
+            });
+            methods.push(quote_spanned! {t.span=>
+                #[allow(unused_variables)]
+                fn #fn_ident(self #params) -> #end {
+                    #body
+                }
+            });
+        }
+
+        if methods.is_empty() {
+            return proc_macro::TokenStream::new();
+        }
+
+        // each table has its own trait, named after the machine like the
+        // handler traits
+        let group = self.options.group.as_ref().map(|group| group.to_string());
+        let trait_name = Ident::new(
+            &format!(
+                "__{}{}{}Defaults",
+                self.machine_name,
+                group.unwrap_or_default(),
+                state
+            ),
+            Span::call_site(),
+        );
+        let path = state_path(self.machine.as_ref(), state);
+
+        let tokens = quote! {
+            #[allow(dead_code)]
+            #[doc(hidden)]
+            trait #trait_name {
+                #(#signatures)*
+            }
+
+            #[allow(dead_code)]
+            impl #trait_name for #path {
+                #(#methods)*
+            }
+        };
+
+        proc_macro::TokenStream::from(tokens)
+    }
+
+    /// transitions grouped by start state, in declaration order
+    fn by_state(&self) -> Vec<(&Ident, Vec<&Transition>)> {
+        let mut states: Vec<(&Ident, Vec<&Transition>)> = Vec::new();
+        for t in self
+            .transitions
+            .iter()
+            .filter(|t| !t.defer && !t.pop && t.history.is_none() && t.delegate.is_none())
+        {
+            match states.iter_mut().find(|(state, _)| *state == &t.start) {
+                Some((_, transitions)) => transitions.push(t),
+                None => states.push((&t.start, vec![t])),
+            }
+        }
+
+        states
+    }
+
+    // handlers with a body in the table
+    fn generate_handlers(&self) -> syn::export::TokenStream {
+        let mut stream = proc_macro::TokenStream::new();
+
+        for (state, transitions) in self.by_state() {
+            if !self.options.traits {
+                stream.extend(self.generate_unit_handlers(state, &transitions));
+            }
+
+            let with_body = transitions.iter().filter(|t| t.body.is_some()).collect::<Vec<_>>();
+            if with_body.is_empty() {
+                continue;
+            }
+
+            let needed = transitions.iter().filter(|t| self.unit_body(t).is_none()).count();
+            if self.options.traits && with_body.len() != needed {
+                let error = Error::new(
+                    state.span(),
+                    format!(
+                        "with the `traits` option, either all or none of the handlers of `{}` must have a body",
+                        state
+                    ),
+                );
+                stream.extend(proc_macro::TokenStream::from(error.to_compile_error()));
+                continue;
+            }
+
+            let methods = with_body.iter().map(|t| {
+                let fn_ident = t.trigger.handler_name(&self.naming());
+                let params = self.handler_params(t, t.span);
+                let end = self.handler_output(t);
+                let args = self.handler_args(t, t.span);
+                let mut body = t.generate_body(self.machine.as_ref(), &args);
+                if let Some(Body::Unit(_)) | Some(Body::Struct { .. }) = t.body {
+                    body = body.map(|body| self.without_output(body));
+                }
+                let visibility = if self.options.traits { quote!() } else { quote!(pub) };
+
+                // spanned so that `self` and `input` resolve in the user's block
+                quote_spanned! {t.span=>
+                    #[allow(unused_variables)]
+                    #visibility fn #fn_ident(self #params) -> #end {
+                        #body
+                    }
+                }
+            });
+
+            let path = state_path(self.machine.as_ref(), state);
+            let tokens = if self.options.traits {
+                let trait_name = handlers_trait_name(&self.machine_name, state);
+                quote! {
+                    impl #trait_name for #path {
+                        #(#methods)*
+                    }
+                }
+            } else {
+                quote! {
+                    impl #path {
+                        #(#methods)*
+                    }
+                }
+            };
+
+            stream.extend(proc_macro::TokenStream::from(tokens));
+        }
+
+        stream
+    }
+}
+
+impl Transition {
+    /// type returned by the state's handler
+    fn handler_end(
+        &self,
+        machine: Option<&Machine>,
+        machine_name: &Ident,
+    ) -> syn::export::TokenStream2 {
+        match &self.outcome {
+            Some(outcome) => quote!(#outcome),
+            None if self.end.len() == 1 => state_path(machine, &self.end[0]),
+            None => quote!(#machine_name),
+        }
+    }
+
+    // checks the members listed in a struct body against the end state, and
+    // that the missing ones can be carried over from the start state
+    fn validate_struct_body(&self, machine: &Machine) -> Vec<Error> {
+        let mut errors = Vec::new();
+
+        let (fields, carry) = match &self.body {
+            Some(Body::Struct { fields, carry }) => (fields, carry),
+            Some(Body::Unit(span)) => {
+                let end = &self.end[0];
+                if machine.fields(end).unwrap().iter().next().is_some() {
+                    errors.push(Error::new(
+                        *span,
+                        format!("`{{}}` cannot build `{}`, its fields must be given", end),
+                    ));
+                }
+                return errors;
+            }
+            _ => return errors,
+        };
+
+        let end = &self.end[0];
+        let end_fields = machine.fields(end).unwrap();
+
+        for field in fields.iter() {
+            let known = match &field.member {
+                Member::Named(ident) => end_fields.iter().any(|f| f.ident.as_ref() == Some(ident)),
+                Member::Unnamed(_) => false,
+            };
+
+            if !known {
+                let member = &field.member;
+                errors.push(Error::new_spanned(
+                    member,
+                    format!("state `{}` has no field `{}`", end, quote!(#member)),
+                ));
+            }
+        }
+
+        if let Some(carry) = carry {
+            let start_fields = match machine.fields(&self.start) {
+                Some(start_fields) => start_fields,
+                None => {
+                    errors.push(Error::new(
+                        carry.span(),
+                        format!("members cannot be carried over from superstate `{}`", self.start),
+                    ));
+                    return errors;
+                }
+            };
+
+            for field in end_fields.iter() {
+                let ident = match &field.ident {
+                    Some(ident) => ident,
+                    None => {
+                        errors.push(Error::new(
+                            carry.span(),
+                            format!("members cannot be carried over to the tuple state `{}`", end),
+                        ));
+                        break;
+                    }
+                };
+                if given(fields, ident) {
+                    continue;
+                }
+
+                let ty = &field.ty;
+                let carried = start_fields.iter().any(|f| {
+                    let start_ty = &f.ty;
+                    f.ident.as_ref() == Some(ident)
+                        && quote!(#start_ty).to_string() == quote!(#ty).to_string()
+                });
+
+                if !carried {
+                    errors.push(Error::new(
+                        carry.span(),
+                        format!(
+                            "field `{}` of `{}` cannot be carried over from `{}` and must be given",
+                            ident, end, self.start
+                        ),
+                    ));
+                }
+            }
+        }
+
+        errors
+    }
+
+    fn generate_body(
+        &self,
+        machine: Option<&Machine>,
+        args: &[syn::export::TokenStream2],
+    ) -> Option<syn::export::TokenStream2> {
+        let end = &self.end[0];
+        let path = state_path(machine, end);
+        let span = self.span;
+
+        let tokens = match self.body.as_ref()? {
+            Body::Unit(_) => quote!(#path {}),
+            Body::Block(block) => {
+                let stmts = &block.stmts;
+                quote!(#(#stmts)*)
+            }
+            Body::Closure(closure) => {
+                let receiver = quote_spanned!(span=> self);
+                let names = Some(&receiver).into_iter().chain(args.iter());
+                let bindings = closure.inputs.iter().zip(names).map(|(arg, value)| {
+                    match arg {
+                        FnArg::Captured(a) => {
+                            let pat = &a.pat;
+                            let ty = &a.ty;
+                            quote!(let #pat: #ty = #value;)
+                        }
+                        FnArg::Inferred(pat) => quote!(let #pat = #value;),
+                        _ => quote!(),
+                    }
+                });
+                let body = &closure.body;
+
+                quote! {
+                    #(#bindings)*
+                    #body
+                }
+            }
+            Body::Struct { fields, carry } => {
+                let carried = match (carry, machine.and_then(|m| m.fields(end))) {
+                    (Some(_), Some(end_fields)) => end_fields
+                        .iter()
+                        .filter_map(|f| f.ident.as_ref())
+                        .filter(|ident| !given(fields, ident))
+                        .collect::<Vec<_>>(),
+                    _ => Vec::new(),
+                };
+                let receiver = quote_spanned!(span=> self);
+                let carried = carried.iter().map(|ident| quote!(#ident: #receiver.#ident));
+                let fields = fields.iter().map(|f| quote!(#f)).chain(carried);
+
+                quote!(#path { #(#fields),* })
+            }
+        };
+
+        Some(tokens)
+    }
+}
+
+impl Trigger {
+    pub fn message(&self) -> Option<&Ident> {
+        match self {
+            Trigger::Message(message) => Some(message),
+            _ => None,
+        }
+    }
+
+    fn is_after(&self) -> bool {
+        matches!(self, Trigger::After(_))
+    }
+
+    /// whether both triggers would fire the same transition
+    fn same(&self, other: &Trigger) -> bool {
+        match (self, other) {
+            (Trigger::Message(a), Trigger::Message(b)) => a == b,
+            (Trigger::After(_), Trigger::After(_)) => true,
+            (Trigger::Completion, Trigger::Completion) => true,
+            (Trigger::Child { field: a, error: e }, Trigger::Child { field: b, error: f }) => {
+                a == b && e == f
+            }
+            _ => false,
+        }
+    }
+
+    fn handler_name(&self, naming: &Naming) -> Ident {
+        match self {
+            Trigger::Message(message) => naming.handler(message),
+            Trigger::After(_) => naming.handler_suffix("timeout"),
+            Trigger::Completion => naming.handler_suffix("complete"),
+            Trigger::Child { field, error } => {
+                let kind = if *error { "error" } else { "final" };
+                naming.handler_suffix(&format!("{}_{}", field, kind))
+            }
+        }
+    }
+
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trigger::Message(message) => write!(f, "{}", message),
+            Trigger::After(delay) => {
+                write!(f, "after({})", quote!(#delay).to_string().replace(' ', ""))
+            }
+            Trigger::Completion => write!(f, "_"),
+            Trigger::Child { field, error: false } => write!(f, "on_final({})", field),
+            Trigger::Child { field, error: true } => write!(f, "on_error({})", field),
+        }
+    }
+}
+
+// the `// group X` sections of a graph, with their name
+fn dot_sections(dot: &str) -> Vec<(String, String)> {
+    let mut sections: Vec<(String, String)> = Vec::new();
+    let mut current: Option<(String, String)> = None;
+
+    for line in dot.lines() {
+        let trimmed = line.trim();
+        if let Some((name, mut section)) = current.take() {
+            section.push_str(line);
+            section.push('\n');
+            if trimmed == format!("// end of group {}", name) {
+                sections.push((name, section));
+            } else {
+                current = Some((name, section));
+            }
+        } else if let Some(name) = trimmed.strip_prefix("// group ") {
+            current = Some((name.to_string(), format!("{}\n", line)));
+        }
+    }
+
+    sections
+}
+
+// the lines of a graph outside of the groups' sections, without the closing
+// brace of the graph
+fn main_dot_lines(dot: &str) -> String {
+    let mut lines = Vec::new();
+    let mut in_group = false;
+
+    for line in dot.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("// group ") {
+            in_group = true;
+        } else if trimmed.starts_with("// end of group ") {
+            in_group = false;
+        } else if !in_group {
+            lines.push(line);
+        }
+    }
+
+    if lines.last().is_some_and(|line| line.trim() == "}") {
+        lines.pop();
+    }
+
+    let mut string = String::new();
+    for line in lines {
+        string.push_str(line);
+        string.push('\n');
+    }
+    string
+}
+
+/// the path of the machine held by the `field` of `state`
+fn child_type<'a>(machine: &'a Machine, state: &Ident, field: &Ident) -> Option<&'a Path> {
+    machine
+        .fields(state)?
+        .iter()
+        .find(|f| f.ident.as_ref() == Some(field))
+        .and_then(|f| match &f.ty {
+            Type::Path(ty) if ty.qself.is_none() => Some(&ty.path),
+            _ => None,
+        })
+}
+
+fn given(fields: &[FieldValue], ident: &Ident) -> bool {
+    fields.iter().any(|f| match &f.member {
+        Member::Named(i) => i == ident,
+        Member::Unnamed(_) => false,
+    })
+}
+
+fn is_struct_body(input: ParseStream) -> bool {
+    starts_struct_body(&input.fork()).unwrap_or(false)
+}
+
+// a brace group starting with `member:` or `..` contains struct members, not
+// statements, as does one with shorthand members followed by `..carry`
+fn starts_struct_body(input: ParseStream) -> Result<bool> {
+    let content;
+    braced!(content in input);
+
+    Ok(content.peek(Token![..])
+        || (content.peek(Ident) && content.peek2(Token![:]) && !content.peek2(Token![::]))
+        || (content.peek(Ident) && ends_with_carry(&content)))
+}
+
+// `{ y, ..carry }`, which would otherwise be parsed as a block
+fn ends_with_carry(content: ParseStream) -> bool {
+    let fork = content.fork();
+    loop {
+        if fork.peek(Token![..]) {
+            return true;
+        }
+        if fork.parse::<FieldValue>().is_err() || fork.parse::<Token![,]>().is_err() {
+            return false;
+        }
+    }
+}
+
+fn parse_struct_body(input: ParseStream) -> Result<Body> {
+    let content;
+    braced!(content in input);
+
+    let mut fields = Vec::new();
+    let mut carry = None;
+
+    while !content.is_empty() {
+        if content.peek(Token![..]) {
+            let _: Token![..] = content.parse()?;
+            let ident: Ident = content.parse()?;
+            if ident != "carry" {
+                return Err(Error::new(ident.span(), "expected `..carry`"));
+            }
+            carry = Some(ident);
+            break;
+        }
+
+        fields.push(content.parse()?);
+
+        if content.is_empty() {
+            break;
+        }
+        let _: Token![,] = content.parse()?;
+    }
+
+    Ok(Body::Struct { fields, carry })
+}
+
+fn render_cluster(machine: &Machine, superstate: &Ident, depth: usize, string: &mut String) {
+    let indent = "    ".repeat(depth);
+    string.push_str(&format!("{}subgraph cluster_{} {{\n", indent, superstate));
+    string.push_str(&format!("{}    label = \"{}\";\n", indent, superstate));
+
+    for child in machine.children(superstate) {
+        if machine.is_superstate(child) {
+            render_cluster(machine, child, depth + 1, string);
+        } else {
+            string.push_str(&format!("{}    {};\n", indent, child));
+        }
+    }
+
+    string.push_str(&format!("{}}}\n", indent));
+}
+
+// `traffic::Green` when the machine keeps its states in a module
+fn state_path(machine: Option<&Machine>, state: &Ident) -> syn::export::TokenStream2 {
+    match machine {
+        Some(machine) => machine.path(state),
+        None => quote!(#state),
+    }
+}
+
+/// `Connected` is kept in the `connected` field of the history
+fn history_field(superstate: &Ident) -> Ident {
+    Ident::new(&snake_case(&superstate.to_string()), Span::call_site())
+}
+
+/// `StartTimer` is performed by `start_timer`
+fn effect_name(effect: &Ident) -> Ident {
+    Ident::new(&snake_case(&effect.to_string()), effect.span())
+}
+
+/// `TrafficGreenHandlers` for `Green`, named after the machine so that the
+/// machines of a module can have states of the same name
+fn handlers_trait_name(machine_name: &Ident, state: &Ident) -> Ident {
+    Ident::new(&format!("{}{}Handlers", machine_name, state), state.span())
+}
+
+// the right side of a transition, as written in the table
+fn render_end(t: &Transition) -> String {
+    let end = &t.end;
+    if t.defer {
+        "defer".to_string()
+    } else if t.pop {
+        "pop".to_string()
+    } else if let Some(field) = &t.delegate {
+        format!("delegate {}", field)
+    } else if let Some(history) = &t.history {
+        let kind = if history.deep { "deep_history" } else { "history" };
+        format!("{}::{}", history.superstate, kind)
+    } else {
+        let states = if end.len() == 1 {
+            end[0].to_string()
+        } else {
+            let states = end.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            format!("[{}]", states.join(", "))
+        };
+        if t.push {
+            format!("push {}", states)
+        } else {
+            states
+        }
+    }
+}
+
+/// parses the machine definition passed back by the `machine!` description
+/// macro, if present
+pub fn parse_description(input: ParseStream) -> Result<Option<Machine>> {
+    if input.peek(syn::token::Brace) {
+        let content;
+        braced!(content in input);
+        Ok(Some(content.parse()?))
+    } else {
+        Ok(None)
+    }
+}
+
+pub fn undeclared_state(state: &Ident, machine_name: &Ident) -> Error {
+    Error::new(
+        state.span(),
+        format!(
+            "state `{}` is not declared in machine `{}`",
+            state, machine_name
+        ),
+    )
 }
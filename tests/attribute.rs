@@ -0,0 +1,71 @@
+#[macro_use]
+extern crate machine;
+
+#[machine_enum]
+#[derive(Clone,Debug,PartialEq)]
+enum Traffic {
+  Green { count: u8 },
+  Orange,
+  Red,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+transitions!(Traffic,
+  [
+    (Green, Advance) => Orange {},
+    (Orange, Advance) => Red {},
+    (Red, Advance) => Green { count: 0 }
+  ]
+);
+
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Ctx {
+  toggles: u32,
+}
+
+#[machine_enum(context = Ctx)]
+#[derive(Clone,Debug,PartialEq)]
+#[output(bool)]
+enum Switch {
+  #[output(false)]
+  Off,
+  #[output(true)]
+  On,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Toggle;
+
+transitions!(Switch,
+  [
+    (Off, Toggle) => On |_, ctx, _| {
+      ctx.toggles += 1;
+      On {}
+    },
+    (On, Toggle) => Off |_, ctx, _| {
+      ctx.toggles += 1;
+      Off {}
+    }
+  ]
+);
+
+#[test]
+fn attribute() {
+  let t = Traffic::green(2);
+  assert_eq!(t, Traffic::Green(Green { count: 2 }));
+  let t = t.on_advance(Advance).on_advance(Advance);
+  assert_eq!(t, Traffic::red());
+  assert_eq!(t.on_advance(Advance), Traffic::green(0));
+}
+
+#[test]
+fn attribute_options() {
+  let mut ctx = Ctx::default();
+  let s = Switch::off().on_toggle(&mut ctx, Toggle);
+  assert!(s.output());
+  let s = s.on_toggle(&mut ctx, Toggle);
+  assert!(!s.output());
+  assert_eq!(ctx.toggles, 2);
+}
@@ -0,0 +1,58 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum State {
+    Start { pub x: u8, pub name: String },
+    End { pub x: u8, pub name: String, y: bool },
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Msg1;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Msg2 { x: u8 }
+
+transitions!(State,
+  [
+    (Start, Msg1) => End { y: true, ..carry },
+    (End, Msg1) => End { y: !self.y, ..carry },
+    (End, Msg2) => Start { x: input.x, ..carry }
+  ]
+);
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Upload {
+    Idle { retries: u8 },
+    Sending { retries: u8, input: Chunk },
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Chunk(Vec<u8>);
+
+transitions!(Upload,
+  [
+    (Idle, Chunk) => Sending { input, ..carry }
+  ]
+);
+
+#[test]
+fn carry() {
+  let mut s = State::start(1, "a".to_string());
+  s = s.on_msg1(Msg1);
+  assert_eq!(s, State::end(1, "a".to_string(), true));
+  s = s.on_msg1(Msg1);
+  assert_eq!(s, State::end(1, "a".to_string(), false));
+  s = s.on_msg2(Msg2 { x: 3 });
+  assert_eq!(s, State::start(3, "a".to_string()));
+}
+
+#[test]
+fn carry_shorthand() {
+  let u = Upload::idle(2).on_chunk(Chunk(vec![1, 2]));
+  assert_eq!(u, Upload::sending(2, Chunk(vec![1, 2])));
+}
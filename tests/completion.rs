@@ -0,0 +1,111 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  pub enum Form {
+    Editing { text: String },
+    Validating { text: String },
+    Valid { text: String },
+    Invalid,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Submit;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Edit { text: String }
+
+pub enum Validated {
+  Valid(Valid),
+  Invalid(Invalid),
+}
+
+transitions!(Form,
+  [
+    (Editing, Submit) => Validating { ..carry },
+    (Validating, _) => [Valid, Invalid] as Validated |state| {
+      if state.text.is_empty() {
+        Validated::Invalid(Invalid {})
+      } else {
+        Validated::Valid(Valid { text: state.text })
+      }
+    },
+    (Invalid, Edit) => Editing |_, input| Editing { text: input.text }
+  ]
+);
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Loop {
+    Idle,
+    Ping,
+    Pong,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Start;
+
+transitions!(Loop, max_steps = 4,
+  [
+    (Idle, Start) => Ping {},
+    (Ping, _) => Pong {},
+    (Pong, _) => Ping {}
+  ]
+);
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Door {
+    Closed,
+    Opening,
+    Open,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Push;
+
+#[handlers(Door)]
+mod door_handlers {
+  use super::*;
+
+  impl Closed {
+    pub fn on_push(self, _: Push) -> Opening {
+      Opening {}
+    }
+  }
+
+  impl Opening {
+    pub fn on_complete(self) -> Open {
+      Open {}
+    }
+  }
+}
+
+#[test]
+fn settles() {
+  let f = Form::editing("hello".to_string()).on_submit(Submit);
+  assert_eq!(f, Form::valid("hello".to_string()));
+
+  let f = Form::editing(String::new()).on_submit(Submit);
+  assert_eq!(f, Form::invalid());
+
+  let f = f.on_edit(Edit { text: "hi".to_string() });
+  assert_eq!(f, Form::editing("hi".to_string()));
+
+  assert_eq!(Form::validating(String::new()).complete(), Form::invalid());
+  assert_eq!(Form::editing(String::new()).complete(), Form::editing(String::new()));
+}
+
+#[test]
+fn step_limit() {
+  assert_eq!(Loop::idle().on_start(Start), Loop::error());
+}
+
+#[test]
+fn inferred() {
+  assert_eq!(Door::closed().on_push(Push), Door::open());
+}
@@ -0,0 +1,101 @@
+#[macro_use]
+extern crate machine;
+
+use std::time::Duration;
+
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Ctx {
+  cars: u32,
+  max_cars: u8,
+  log: Vec<String>,
+}
+
+machine!(
+  context = Ctx,
+  #[derive(Clone,Debug,PartialEq)]
+  enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct PassCar { count: u8 }
+
+transitions!(Traffic,
+  [
+    (Green, Advance) => Orange,
+    (Green, PassCar) => [Green, Orange],
+    (Orange, after(Duration::from_secs(5))) => Red |_, ctx| {
+      ctx.log.push("red".to_string());
+      Red {}
+    },
+    (Red, Advance) => Green { count: 0 }
+  ]
+);
+
+impl Green {
+  pub fn on_advance(self, ctx: &mut Ctx, _: Advance) -> Orange {
+    ctx.log.push("orange".to_string());
+    Orange {}
+  }
+
+  pub fn on_pass_car(self, ctx: &mut Ctx, input: PassCar) -> Traffic {
+    ctx.cars += u32::from(input.count);
+    let count = self.count + input.count;
+    if count >= ctx.max_cars {
+      Traffic::orange()
+    } else {
+      Traffic::green(count)
+    }
+  }
+}
+
+methods!(Traffic,
+  [
+    [Green, Orange, Red] => fn describe(&self) -> String
+  ]
+);
+
+impl Green {
+  pub fn describe(&self, ctx: &mut Ctx) -> String {
+    format!("green, {} cars so far", ctx.cars)
+  }
+}
+
+impl Orange {
+  pub fn describe(&self, _: &mut Ctx) -> String {
+    "orange".to_string()
+  }
+}
+
+impl Red {
+  pub fn describe(&self, _: &mut Ctx) -> String {
+    "red".to_string()
+  }
+}
+
+#[test]
+fn context() {
+  let mut ctx = Ctx { max_cars: 10, ..Ctx::default() };
+
+  let t = Traffic::green(0).on_pass_car(&mut ctx, PassCar { count: 4 });
+  assert_eq!(t, Traffic::green(4));
+  assert_eq!(t.describe(&mut ctx), Some("green, 4 cars so far".to_string()));
+
+  let t = t.on_pass_car(&mut ctx, PassCar { count: 6 });
+  assert_eq!(t, Traffic::orange());
+  assert_eq!(ctx.cars, 10);
+
+  let entered_at = std::time::Instant::now();
+  let t = t.on_tick(&mut ctx, entered_at, entered_at + Duration::from_secs(5));
+  assert_eq!(t, Traffic::red());
+
+  let t = t.on_advance(&mut ctx, Advance).on_advance(&mut ctx, Advance);
+  assert_eq!(t, Traffic::orange());
+  assert_eq!(ctx.log, vec!["red".to_string(), "orange".to_string()]);
+}
@@ -0,0 +1,133 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Handshake {
+    Hello,
+    KeyExchange { rounds: u8 },
+    Done,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct HandshakeMsg { ok: bool }
+
+transitions!(Handshake, final_states,
+  [
+    (Hello, HandshakeMsg) => KeyExchange { rounds: 0 },
+    (KeyExchange, HandshakeMsg) => [KeyExchange, Done]
+  ]
+);
+
+impl KeyExchange {
+  pub fn on_handshake_msg(self, input: HandshakeMsg) -> Handshake {
+    if !input.ok {
+      Handshake::error()
+    } else if self.rounds == 1 {
+      Handshake::done()
+    } else {
+      Handshake::key_exchange(self.rounds + 1)
+    }
+  }
+}
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Session {
+    Connecting { handshake: Handshake },
+    Established,
+    Failed,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Disconnect;
+
+transitions!(Session, final_states,
+  [
+    (Connecting, HandshakeMsg) => delegate handshake,
+    (Connecting, on_final(handshake)) => Established {},
+    (Connecting, on_error(handshake)) => Failed {},
+    (Connecting, Disconnect) => Failed {},
+    (Established, Disconnect) => Failed {}
+  ]
+);
+
+machine!(
+  #[machine(handler_prefix = "handle_")]
+  #[derive(Clone,Debug,PartialEq)]
+  enum Login {
+    Prompt,
+    LoggedIn,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Password;
+
+transitions!(Login, final_states,
+  [
+    (Prompt, Password) => LoggedIn
+  ]
+);
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Terminal {
+    Locked { login: Login },
+    Unlocked,
+  }
+);
+
+transitions!(Terminal,
+  [
+    (Locked, Password) => delegate login,
+    (Locked, on_final(login)) => Unlocked
+  ]
+);
+
+fn connecting() -> Session {
+  Session::connecting(Handshake::hello())
+}
+
+#[test]
+fn delegate_to_child() {
+  let s = connecting().on_handshake_msg(HandshakeMsg { ok: true });
+  assert_eq!(s, Session::connecting(Handshake::key_exchange(0)));
+
+  let s = s.on_handshake_msg(HandshakeMsg { ok: true });
+  assert_eq!(s, Session::connecting(Handshake::key_exchange(1)));
+
+  // the child reaches `Done`, which has no transition
+  let s = s.on_handshake_msg(HandshakeMsg { ok: true });
+  assert_eq!(s, Session::established());
+}
+
+#[test]
+fn child_error() {
+  let s = connecting().on_handshake_msg(HandshakeMsg { ok: true });
+  let s = s.on_handshake_msg(HandshakeMsg { ok: false });
+  assert_eq!(s, Session::failed());
+}
+
+#[test]
+fn other_messages() {
+  assert_eq!(connecting().on_disconnect(Disconnect), Session::failed());
+  assert_eq!(Session::established().on_handshake_msg(HandshakeMsg { ok: true }), Session::error());
+}
+
+#[test]
+fn final_states() {
+  assert!(Handshake::done().is_final());
+  assert!(!Handshake::hello().is_final());
+  assert!(!Handshake::error().is_final());
+  assert!(Session::failed().is_final());
+}
+
+#[test]
+fn child_handler_prefix() {
+  // the child's own prefix names the method called on it
+  let t = Terminal::locked(Login::prompt()).on_password(Password);
+  assert_eq!(t, Terminal::unlocked());
+}
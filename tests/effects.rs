@@ -0,0 +1,120 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct StartTimer { seconds: u64 }
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Log { line: String }
+
+transitions!(Traffic, effects = [StartTimer, Log],
+  [
+    (Green, Advance) => Orange,
+    (Orange, Advance) => Red |_, _| (Red {}, vec![StartTimer { seconds: 30 }.into()]),
+    (Red, Advance) => Green { count: 0 }
+  ]
+);
+
+impl Green {
+  pub fn on_advance(self, _: Advance) -> (Orange, Vec<TrafficEffects>) {
+    let log = Log { line: format!("{} cars passed", self.count) };
+    (Orange {}, vec![log.into(), StartTimer { seconds: 5 }.into()])
+  }
+}
+
+#[derive(Default)]
+struct Recorder {
+  timers: Vec<u64>,
+  lines: Vec<String>,
+}
+
+impl TrafficEffectExecutor for Recorder {
+  fn start_timer(&mut self, effect: StartTimer) {
+    self.timers.push(effect.seconds);
+  }
+
+  fn log(&mut self, effect: Log) {
+    self.lines.push(effect.line);
+  }
+}
+
+machine!(
+  #[derive(Debug)]
+  enum Door {
+    Open,
+    Closed,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Close;
+
+// neither `Clone` nor `PartialEq`, like the machine
+#[derive(Debug)]
+pub struct Notify { id: u8 }
+
+transitions!(Door, effects = [Notify],
+  [
+    (Open, Close) => Closed
+  ]
+);
+
+impl Open {
+  pub fn on_close(self, _: Close) -> (Closed, Vec<DoorEffects>) {
+    (Closed {}, vec![Notify { id: 1 }.into()])
+  }
+}
+
+#[test]
+fn effects() {
+  let (t, effects) = Traffic::green(3).on_advance(Advance);
+  assert_eq!(t, Traffic::orange());
+  assert_eq!(
+    effects,
+    vec![
+      TrafficEffects::Log(Log { line: "3 cars passed".to_string() }),
+      TrafficEffects::StartTimer(StartTimer { seconds: 5 }),
+    ]
+  );
+
+  let (t, more) = t.on_advance(Advance);
+  assert_eq!(t, Traffic::red());
+
+  let (t, none) = t.on_advance(Advance);
+  assert_eq!(t, Traffic::green(0));
+  assert!(none.is_empty());
+
+  let mut recorder = Recorder::default();
+  recorder.execute_all(effects);
+  recorder.execute_all(more);
+  assert_eq!(recorder.timers, vec![5, 30]);
+  assert_eq!(recorder.lines, vec!["3 cars passed".to_string()]);
+}
+
+impl DoorEffectExecutor for Vec<u8> {
+  fn notify(&mut self, effect: Notify) {
+    self.push(effect.id);
+  }
+}
+
+#[test]
+fn effects_derive_like_the_machine() {
+  let (d, effects) = Door::open().on_close(Close);
+  assert!(matches!(d, Door::Closed(_)));
+
+  let mut notified = Vec::new();
+  notified.execute_all(effects);
+  assert_eq!(notified, vec![1]);
+}
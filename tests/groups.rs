@@ -0,0 +1,146 @@
+#[macro_use]
+extern crate machine;
+
+use pedestrian::TrafficPedestrianMessages;
+
+// visible in `pedestrian`, where the group's enum is generated
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  pub(crate) enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red,
+    Crossing,
+    Off,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Walk;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Crossed;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Shutdown;
+
+// the handlers of the member-less states are inferred in each table, some
+// for the same start states
+transitions!(Traffic, final_states, groups = [Pedestrian],
+  [
+    (Green, Advance) => Orange,
+    (Orange, Advance) => Red,
+    (Red, Advance) => Green { count: 0 }
+  ]
+);
+
+mod pedestrian {
+  use super::*;
+
+  transitions!(Traffic, group = Pedestrian,
+    [
+      (Red, Walk) => Crossing,
+      (Crossing, Crossed) => Red
+    ]
+  );
+}
+
+transitions!(Traffic, group = Maintenance,
+  [
+    (Green, Shutdown) => Off,
+    (Red, Shutdown) => Off
+  ]
+);
+
+#[test]
+fn group_methods() {
+  let t = Traffic::red().on_walk(Walk);
+  assert_eq!(t, Traffic::crossing());
+  assert_eq!(t.on_crossed(Crossed), Traffic::red());
+  assert_eq!(Traffic::green(0).on_walk(Walk), Traffic::error());
+}
+
+#[test]
+fn group_dispatch() {
+  let t = Traffic::red().dispatch_pedestrian(Walk.into());
+  assert_eq!(t, Traffic::crossing());
+
+  let t = Traffic::red().dispatch_maintenance(TrafficMaintenanceMessages::Shutdown(Shutdown));
+  assert_eq!(t, Traffic::off());
+}
+
+#[test]
+fn aggregate() {
+  let messages: Vec<TrafficMessages> = vec![
+    TrafficMessages::Advance(Advance),
+    TrafficMessages::Advance(Advance),
+    TrafficPedestrianMessages::from(Walk).into(),
+    TrafficPedestrianMessages::from(Crossed).into(),
+  ];
+
+  let t = messages
+    .into_iter()
+    .fold(Traffic::green(0), |t, message| t.dispatch(message));
+  assert_eq!(t, Traffic::red());
+}
+
+#[test]
+fn final_states() {
+  // `Crossing` is only left through the pedestrian table
+  assert!(!Traffic::crossing().is_final());
+  assert!(Traffic::off().is_final());
+}
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Heater {
+    Cold,
+    #[superstate(On)]
+    Low,
+    #[superstate(On)]
+    High,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Switch;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Boost;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Overheat;
+
+transitions!(Heater, groups = [Side],
+  [
+    (Cold, Switch) => Low,
+    (Low, Boost) => High,
+    (On, Switch) => Cold
+  ]
+);
+
+transitions!(Heater, group = Side,
+  [
+    (On, Overheat) => Cold
+  ]
+);
+
+#[test]
+fn merged_dot() {
+  let dot = std::fs::read_to_string("target/heater.dot").unwrap();
+  assert_eq!(dot.matches('{').count(), dot.matches('}').count());
+  assert_eq!(dot.matches("compound = true;").count(), 1);
+  assert_eq!(dot.matches("subgraph cluster_On").count(), 1);
+  assert!(dot.ends_with("}"));
+
+  // the group section only has its edges
+  let section = dot
+    .split("// group Side\n")
+    .nth(1)
+    .and_then(|rest| rest.split("    // end of group Side").next())
+    .unwrap();
+  assert_eq!(section, "    Low -> Cold [ label = \"Overheat\", ltail = cluster_On ];\n");
+}
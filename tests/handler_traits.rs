@@ -0,0 +1,66 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct PassCar { count: u8 }
+
+transitions!(Traffic, traits,
+  [
+    (Green, Advance) => Orange,
+    (Orange, Advance) => Red,
+    (Red, Advance) => Green,
+    (Green, PassCar) => [Green, Orange],
+  ]
+);
+
+impl TrafficGreenHandlers for Green {
+  fn on_advance(self, _: Advance) -> Orange {
+    Orange {}
+  }
+
+  fn on_pass_car(self, input: PassCar) -> Traffic {
+    let count = self.count + input.count;
+    if count >= 10 {
+      Traffic::orange()
+    } else {
+      Traffic::green(count)
+    }
+  }
+}
+
+impl TrafficOrangeHandlers for Orange {
+  fn on_advance(self, _: Advance) -> Red {
+    Red {}
+  }
+}
+
+impl TrafficRedHandlers for Red {
+  fn on_advance(self, _: Advance) -> Green {
+    Green { count: 0 }
+  }
+}
+
+#[test]
+fn handler_traits() {
+  let mut t = Traffic::green(0);
+  t = t.on_pass_car(PassCar { count: 4 });
+  assert_eq!(t, Traffic::green(4));
+  t = t.on_advance(Advance);
+  assert_eq!(t, Traffic::orange());
+  t = t.on_advance(Advance);
+  assert_eq!(t, Traffic::red());
+  t = t.on_pass_car(PassCar { count: 1 });
+  assert_eq!(t, Traffic::error());
+}
@@ -0,0 +1,185 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct PassCar { count: u8 }
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Reset;
+
+#[handlers(Traffic)]
+mod traffic_handlers {
+  use super::*;
+
+  pub enum PassCarOutcome {
+    Green(Green),
+    Orange(Orange),
+  }
+
+  impl Green {
+    pub fn on_advance(self, _: Advance) -> Orange {
+      Orange {}
+    }
+
+    pub fn on_pass_car(self, input: PassCar) -> PassCarOutcome {
+      let count = self.count + input.count;
+      if count >= 10 {
+        PassCarOutcome::Orange(Orange {})
+      } else {
+        PassCarOutcome::Green(Green { count })
+      }
+    }
+
+    #[targets(Green, Red)]
+    pub fn on_reset(self, _: Reset) -> Traffic {
+      if self.count == 0 {
+        Traffic::Green(self)
+      } else {
+        Traffic::red()
+      }
+    }
+  }
+
+  impl Orange {
+    pub fn on_advance(self, _: Advance) -> Red {
+      Red {}
+    }
+  }
+
+  impl Red {
+    pub fn on_advance(self, _: Advance) -> Green {
+      Green { count: 0 }
+    }
+  }
+}
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Switch {
+    Off,
+    On,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Toggle;
+
+#[handlers(Switch)]
+impl Off {
+  pub fn on_toggle(self, _: Toggle) -> On {
+    On {}
+  }
+}
+
+pub struct Counter {
+  presses: u32,
+}
+
+machine!(
+  context = Counter,
+  #[derive(Clone,Debug,PartialEq)]
+  enum Button {
+    Up,
+    Down,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Press;
+
+#[handlers(Button)]
+impl Up {
+  pub fn on_press(self, ctx: &mut Counter, _: Press) -> Down {
+    ctx.presses += 1;
+    Down {}
+  }
+}
+
+machine!(
+  #[machine(handler_prefix = "handle_")]
+  #[derive(Clone,Debug,PartialEq)]
+  enum Valve {
+    Closed,
+    Open,
+    Draining,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Turn;
+
+#[handlers(Valve)]
+mod valve_handlers {
+  use super::*;
+
+  impl Closed {
+    pub fn handle_turn(self, _: Turn) -> Open {
+      Open {}
+    }
+  }
+
+  impl Open {
+    pub fn handle_turn(self, _: Turn) -> Draining {
+      Draining {}
+    }
+  }
+
+  impl Draining {
+    pub fn handle_complete(self) -> Closed {
+      Closed {}
+    }
+  }
+}
+
+#[test]
+fn module_handlers() {
+  let mut t = Traffic::green(0);
+  t = t.on_pass_car(PassCar { count: 4 });
+  assert_eq!(t, Traffic::green(4));
+  t = t.on_pass_car(PassCar { count: 6 });
+  assert_eq!(t, Traffic::orange());
+  t = t.on_advance(Advance);
+  t = t.on_advance(Advance);
+  assert_eq!(t, Traffic::green(0));
+  t = t.on_reset(Reset);
+  assert_eq!(t, Traffic::green(0));
+  t = t.on_pass_car(PassCar { count: 1 });
+  t = t.on_reset(Reset);
+  assert_eq!(t, Traffic::red());
+  t = t.on_reset(Reset);
+  assert_eq!(t, Traffic::error());
+}
+
+#[test]
+fn impl_handlers() {
+  let s = Switch::off().on_toggle(Toggle);
+  assert_eq!(s, Switch::on());
+  assert_eq!(s.on_toggle(Toggle), Switch::error());
+}
+
+#[test]
+fn context_handlers() {
+  let mut counter = Counter { presses: 0 };
+  let b = Button::up().on_press(&mut counter, Press);
+  assert_eq!(b, Button::down());
+  assert_eq!(counter.presses, 1);
+}
+
+#[test]
+fn prefixed_handlers() {
+  let v = Valve::closed().handle_turn(Turn);
+  assert_eq!(v, Valve::open());
+  assert_eq!(v.handle_turn(Turn), Valve::closed());
+}
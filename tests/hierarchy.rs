@@ -0,0 +1,91 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Connection {
+    Disconnected,
+    #[superstate(Online)]
+    Authenticating,
+    #[superstate(Online::Connected)]
+    Idle,
+    #[superstate(Online::Connected)]
+    Busy { job: u32 },
+    #[superstate(Online::Connected)]
+    Draining,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Connect;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Authenticated;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Start { job: u32 }
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Drain;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Disconnect;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Ping;
+
+transitions!(Connection,
+  [
+    (Disconnected, Connect) => Authenticating {},
+    (Authenticating, Authenticated) => Idle {},
+    (Idle, Start) => Busy |_, input| Busy { job: input.job },
+    (Busy, Drain) => Draining {},
+    (Online, Disconnect) => Disconnected,
+    (Draining, Disconnect) => Draining {},
+    (Connected, Ping) => [Idle, Busy, Draining]
+  ]
+);
+
+impl Online {
+  pub fn on_disconnect(self, _: Disconnect) -> Disconnected {
+    Disconnected {}
+  }
+}
+
+impl Connected {
+  pub fn on_ping(self, _: Ping) -> Connection {
+    match self {
+      Connected::Idle(state) => Connection::Idle(state),
+      Connected::Busy(state) => Connection::Busy(Busy { job: state.job + 1 }),
+      Connected::Draining(state) => Connection::Draining(state),
+    }
+  }
+}
+
+#[test]
+fn superstates() {
+  let c = Connection::disconnected();
+  assert!(!c.is_in_online());
+
+  let c = c.on_connect(Connect);
+  assert!(c.is_in_online());
+  assert!(!c.is_in_connected());
+
+  let c = c.on_authenticated(Authenticated).on_start(Start { job: 1 });
+  assert_eq!(c, Connection::busy(1));
+  assert!(c.is_in_connected());
+  assert!(c.is_in_online());
+}
+
+#[test]
+fn inherited_transitions() {
+  assert_eq!(Connection::busy(1).on_ping(Ping), Connection::busy(2));
+  assert_eq!(Connection::idle().on_ping(Ping), Connection::idle());
+  assert_eq!(Connection::authenticating().on_ping(Ping), Connection::error());
+
+  assert_eq!(Connection::authenticating().on_disconnect(Disconnect), Connection::disconnected());
+  assert_eq!(Connection::busy(1).on_disconnect(Disconnect), Connection::disconnected());
+  // overridden by the state
+  assert_eq!(Connection::draining().on_disconnect(Disconnect), Connection::draining());
+  assert_eq!(Connection::disconnected().on_disconnect(Disconnect), Connection::error());
+}
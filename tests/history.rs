@@ -0,0 +1,129 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Player {
+    Stopped,
+    Paused,
+    #[superstate(Active)]
+    Loading,
+    #[superstate(Active::Running)]
+    Playing,
+    #[superstate(Active::Running)]
+    Seeking { position: u32 },
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Play;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Loaded;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Seek { position: u32 }
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Pause;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Resume;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct ResumeExactly;
+
+transitions!(Player,
+  [
+    (Stopped, Play) => Loading {},
+    (Loading, Loaded) => Playing {},
+    (Playing, Seek) => Seeking |_, input| Seeking { position: input.position },
+    (Active, Pause) => Paused |_, _| Paused {},
+    (Paused, Resume) => Active::history,
+    (Paused, ResumeExactly) => Active::deep_history
+  ]
+);
+
+#[test]
+fn empty_history() {
+  let mut history = PlayerHistory::new();
+
+  let p = Player::paused().on_resume(&mut history, Resume);
+  assert_eq!(p, Player::loading());
+}
+
+#[test]
+fn shallow_history() {
+  // only the handlers leaving `Active` or going back to it take the history
+  let mut history = PlayerHistory::new();
+
+  let p = Player::stopped()
+    .on_play(Play)
+    .on_pause(&mut history, Pause)
+    .on_resume(&mut history, Resume);
+  assert_eq!(p, Player::loading());
+
+  let p = p
+    .on_loaded(Loaded)
+    .on_seek(Seek { position: 42 })
+    .on_pause(&mut history, Pause);
+  assert_eq!(p, Player::paused());
+
+  // back in `Running`, which is entered in its first state
+  let p = p.on_resume(&mut history, Resume);
+  assert_eq!(p, Player::playing());
+}
+
+#[test]
+fn deep_history() {
+  let mut history = PlayerHistory::new();
+
+  let p = Player::stopped()
+    .on_play(Play)
+    .on_loaded(Loaded)
+    .on_seek(Seek { position: 42 })
+    .on_pause(&mut history, Pause)
+    .on_resume_exactly(&mut history, ResumeExactly);
+  assert_eq!(p, Player::seeking(42));
+}
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Radio {
+    Off,
+    #[superstate(On)]
+    Fm,
+    #[superstate(On)]
+    Am,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Toggle;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Switch;
+
+transitions!(Radio, runner,
+  [
+    (Off, Toggle) => On::history,
+    (Fm, Switch) => Am {},
+    (Am, Switch) => Fm {},
+    (On, Toggle) => Off |_, _, _| Off {}
+  ]
+);
+
+#[test]
+fn runner_history() {
+  // the runner keeps the history of the machine
+  let mut runner = RadioRunner::new(Radio::off());
+  runner.post(Toggle);
+  runner.post(Switch);
+  runner.post(Toggle);
+  runner.run_until_idle();
+  assert_eq!(runner.state(), &Radio::off());
+
+  runner.post(Toggle);
+  runner.run_until_idle();
+  assert_eq!(runner.state(), &Radio::am());
+}
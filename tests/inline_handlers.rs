@@ -0,0 +1,140 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct PassCar { count: u8 }
+
+transitions!(Traffic,
+  [
+    (Green, Advance) => Orange {},
+    (Orange, Advance) => Red { Red {} },
+    (Red, Advance) => Green |_s, _m| Green { count: 0 },
+    (Green, PassCar) => [Green, Orange] {
+      let count = self.count + input.count;
+      if count >= 10 {
+        Traffic::orange()
+      } else {
+        Traffic::green(count)
+      }
+    }
+  ]
+);
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Counter {
+    Counting { total: u32 },
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Add(u32);
+
+transitions!(Counter, traits,
+  [
+    (Counting, Add) => Counting |state: Counting, add: Add| Counting { total: state.total + add.0 }
+  ]
+);
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Door {
+    Open,
+    Closed,
+    Locked { code: u16 },
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Close;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Lock(u16);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Unlock;
+
+transitions!(Door,
+  [
+    (Open, Close) => Closed,
+    (Closed, Lock) => Locked,
+    (Locked, Unlock) => Closed,
+    (Closed, Unlock) => Closed
+  ]
+);
+
+impl Closed {
+  pub fn on_lock(self, input: Lock) -> Locked {
+    Locked { code: input.0 }
+  }
+
+  // written by hand, although it could be inferred
+  pub fn on_unlock(self, _: Unlock) -> Closed {
+    Closed {}
+  }
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Alarm;
+
+// a second table inferring handlers for the same start states
+transitions!(Door, group = Emergency,
+  [
+    (Open, Alarm) => Closed,
+    (Closed, Alarm) => Open
+  ]
+);
+
+#[test]
+fn inline_handlers() {
+  let mut t = Traffic::green(0);
+  t = t.on_pass_car(PassCar { count: 4 });
+  assert_eq!(t, Traffic::green(4));
+  t = t.on_advance(Advance);
+  assert_eq!(t, Traffic::orange());
+  t = t.on_advance(Advance);
+  assert_eq!(t, Traffic::red());
+  t = t.on_advance(Advance);
+  assert_eq!(t, Traffic::green(0));
+  t = t.on_pass_car(PassCar { count: 12 });
+  assert_eq!(t, Traffic::orange());
+}
+
+#[test]
+fn inline_trait_handlers() {
+  let c = Counter::counting(1).on_add(Add(2)).on_add(Add(3));
+  assert_eq!(c, Counter::counting(6));
+}
+
+#[test]
+fn inferred_unit_handlers() {
+  let mut d = Door::open();
+  d = d.on_close(Close);
+  assert_eq!(d, Door::closed());
+  d = d.on_unlock(Unlock);
+  assert_eq!(d, Door::closed());
+  d = d.on_lock(Lock(1234));
+  assert_eq!(d, Door::locked(1234));
+  d = d.on_unlock(Unlock);
+  assert_eq!(d, Door::closed());
+}
+
+#[test]
+fn inferred_unit_handlers_in_groups() {
+  let d = Door::open().on_alarm(Alarm);
+  assert_eq!(d, Door::closed());
+  assert_eq!(d.on_alarm(Alarm), Door::open());
+  assert_eq!(Door::locked(1).on_alarm(Alarm), Door::error());
+}
@@ -0,0 +1,39 @@
+// the macros called by their path, without `#[macro_use]`
+extern crate machine;
+
+machine::machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Lamp {
+    Off,
+    On,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Toggle;
+
+#[machine::handlers(Lamp)]
+impl Off {
+  pub fn on_toggle(self, _: Toggle) -> On {
+    On {}
+  }
+}
+
+machine::methods!(Lamp,
+  [
+    On => fn describe(&self) -> &'static str
+  ]
+);
+
+impl On {
+  pub fn describe(&self) -> &'static str {
+    "on"
+  }
+}
+
+#[test]
+fn path_handlers() {
+  let lamp = Lamp::off().on_toggle(Toggle);
+  assert_eq!(lamp, Lamp::on());
+  assert_eq!(lamp.describe(), Some("on"));
+}
@@ -0,0 +1,65 @@
+#[macro_use]
+extern crate machine;
+
+#[macro_use]
+mod states {
+  machine!(
+    #[derive(Clone,Debug,PartialEq)]
+    pub enum Door {
+      Open,
+      Closed { pub locked: bool },
+    }
+  );
+}
+
+use states::*;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Push;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Lock;
+
+transitions!(Door,
+  [
+    (Open, Push) => Closed,
+    (Closed, Push) => [Open, Closed],
+    (Closed, Lock) => Closed
+  ]
+);
+
+methods!(Door,
+  [
+    Closed => get locked: bool
+  ]
+);
+
+impl Open {
+  pub fn on_push(self, _: Push) -> Closed {
+    Closed { locked: false }
+  }
+}
+
+impl Closed {
+  pub fn on_push(self, _: Push) -> Door {
+    if self.locked {
+      Door::Closed(self)
+    } else {
+      Door::open()
+    }
+  }
+
+  pub fn on_lock(self, _: Lock) -> Closed {
+    Closed { locked: true }
+  }
+}
+
+#[test]
+fn machine_in_macro_use_module() {
+  let mut door = Door::open();
+  door = door.on_push(Push);
+  assert_eq!(door.locked(), Some(&false));
+  door = door.on_lock(Lock);
+  door = door.on_push(Push);
+  assert_eq!(door, Door::closed(true));
+}
@@ -0,0 +1,63 @@
+#[macro_use]
+extern crate machine;
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Lamp {
+  Off,
+  Green,
+  Orange,
+  Red,
+}
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  #[output(error = Lamp::Off)]
+  enum Traffic {
+    #[output(Lamp::Green)]
+    Green { count: u8 },
+    #[output(Lamp::Orange)]
+    Orange,
+    #[output(Lamp::Red)]
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+transitions!(Traffic,
+  [
+    (Green, Advance) => Orange {},
+    (Orange, Advance) => Red {},
+    (Red, Advance) => Green |_| Green { count: 0 }
+  ]
+);
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  #[output(u8)]
+  enum Dimmer {
+    #[output(0)]
+    Off,
+    #[output(255)]
+    On,
+  }
+);
+
+#[test]
+fn outputs() {
+  let t = Traffic::green(0);
+  assert_eq!(t.output(), Lamp::Green);
+  let t = t.on_advance(Advance);
+  assert_eq!(t.output(), Lamp::Orange);
+  let t = t.on_advance(Advance);
+  assert_eq!(t.output(), Lamp::Red);
+  assert_eq!(Traffic::error().output(), Lamp::Off);
+}
+
+#[test]
+fn default_error_output() {
+  assert_eq!(Dimmer::off().output(), 0);
+  assert_eq!(Dimmer::on().output(), 255);
+  assert_eq!(Dimmer::error().output(), 0);
+}
@@ -0,0 +1,102 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[machine(mod = traffic)]
+  #[derive(Clone,Debug,PartialEq)]
+  enum Traffic {
+    Idle,
+    Green { count: u8 },
+    Red,
+  }
+);
+
+// a second machine with a state of the same name
+machine!(
+  #[machine(mod = pump)]
+  #[derive(Clone,Debug,PartialEq)]
+  enum Pump {
+    Idle,
+    Running { speed: u8 },
+  }
+);
+
+// a message with the name of a state
+#[derive(Clone,Debug,PartialEq)]
+pub struct Green;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct PassCar { count: u8 }
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Start;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Stop;
+
+// both machines get an `Idle` handlers trait, and default handlers for `Idle`
+transitions!(Traffic, traits,
+  [
+    (Idle, Green) => Green { count: 0 },
+    (Idle, Stop) => Red,
+    (Green, PassCar) => [Green, Red],
+    (Red, Green) => Green { count: 0 }
+  ]
+);
+
+transitions!(Pump, traits,
+  [
+    (Idle, Start) => Running,
+    (Idle, Stop) => Idle,
+    (Running, Stop) => Idle
+  ]
+);
+
+methods!(Traffic,
+  [
+    Green => get count: u8
+  ]
+);
+
+impl TrafficGreenHandlers for traffic::Green {
+  fn on_pass_car(self, input: PassCar) -> Traffic {
+    let count = self.count + input.count;
+    if count >= 10 {
+      Traffic::red()
+    } else {
+      Traffic::green(count)
+    }
+  }
+}
+
+impl PumpIdleHandlers for pump::Idle {
+  fn on_start(self, _: Start) -> pump::Running {
+    pump::Running { speed: 1 }
+  }
+}
+
+// `on_stop` is inferred, as `Idle` has no members
+impl PumpRunningHandlers for pump::Running {}
+
+#[test]
+fn states_in_module() {
+  let t = Traffic::idle();
+  assert_eq!(t, Traffic::Idle(traffic::Idle {}));
+
+  let t = t.on_green(Green);
+  assert_eq!(t.count(), Some(&0));
+  let t = t.on_pass_car(PassCar { count: 4 });
+  assert_eq!(t, Traffic::Green(traffic::Green { count: 4 }));
+  let t = t.on_pass_car(PassCar { count: 6 });
+  assert_eq!(t, Traffic::red());
+}
+
+#[test]
+fn same_state_names() {
+  let p = Pump::idle().on_start(Start);
+  assert_eq!(p, Pump::Running(pump::Running { speed: 1 }));
+  assert_eq!(Traffic::idle().on_green(Green), Traffic::green(0));
+
+  assert_eq!(Traffic::idle().on_stop(Stop), Traffic::red());
+  assert_eq!(p.on_stop(Stop), Pump::idle());
+}
@@ -0,0 +1,115 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[machine(
+    handler_prefix = "handle_",
+    messages = ClientInput,
+    error = Failed,
+    setter_suffix = "_ref",
+    constructor_prefix = "new_"
+  )]
+  #[derive(Clone,Debug,PartialEq)]
+  enum Client {
+    Idle,
+    AwaitingHTTPResponse { retries: u8 },
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct HTTPRequest;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct IOError;
+
+transitions!(Client, runner,
+  [
+    (Idle, HTTPRequest) => AwaitingHTTPResponse { retries: 0 },
+    (AwaitingHTTPResponse, IOError) => AwaitingHTTPResponse { retries: self.retries + 1 }
+  ]
+);
+
+methods!(Client,
+  [
+    AwaitingHTTPResponse => get retries: u8,
+    AwaitingHTTPResponse => set retries: u8
+  ]
+);
+
+#[test]
+fn configured_names() {
+  let mut runner = ClientRunner::new(Client::new_idle());
+  runner.handle_http_request(HTTPRequest);
+  assert_eq!(runner.state(), &Client::new_awaiting_http_response(0));
+
+  runner.handle_io_error(IOError);
+  let mut c = runner.into_state();
+  assert_eq!(c.retries(), Some(&1));
+  *c.retries_ref().unwrap() = 5;
+  assert_eq!(c.retries(), Some(&5));
+
+  // no transition for this message in `Idle`
+  let mut runner = ClientRunner::new(Client::new_idle());
+  runner.handle_io_error(IOError);
+  let c = runner.into_state();
+  assert_eq!(c, Client::Failed);
+  assert_eq!(c, Client::new_failed());
+  assert!(c.is_error());
+}
+
+#[test]
+fn configured_messages_enum() {
+  let mut runner = ClientRunner::new(Client::new_idle());
+  runner.post(ClientInput::HTTPRequest(HTTPRequest));
+  runner.post(IOError);
+  runner.run_until_idle();
+  assert_eq!(runner.state(), &Client::new_awaiting_http_response(1));
+}
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Parser {
+    A,
+    ABC,
+    Vec2D,
+    ReadURL,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct B;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct XYZ;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Point3D;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct FetchURL;
+
+transitions!(Parser,
+  [
+    (A, B) => ABC,
+    (ABC, XYZ) => Vec2D,
+    (Vec2D, Point3D) => ReadURL,
+    (ReadURL, FetchURL) => A,
+    (ReadURL, IOError) => A
+  ]
+);
+
+#[test]
+fn snake_case_names() {
+  // single letters, whole acronyms, digits and trailing acronyms
+  let p = Parser::a().on_b(B);
+  assert_eq!(p, Parser::abc());
+
+  let p = p.on_xyz(XYZ);
+  assert_eq!(p, Parser::vec2_d());
+
+  let p = p.on_point3_d(Point3D);
+  assert_eq!(p, Parser::read_url());
+
+  assert_eq!(p.clone().on_fetch_url(FetchURL), Parser::a());
+  assert_eq!(p.on_io_error(IOError), Parser::a());
+}
@@ -0,0 +1,143 @@
+#[macro_use]
+extern crate machine;
+
+use std::time::Duration;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  pub enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct PassCar { count: u8 }
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum Command {
+  Beep,
+  StopTraffic,
+}
+
+pub enum PassCarOutcome {
+  Green(Green),
+  Orange(Orange),
+}
+
+transitions!(Traffic, output = Command,
+  [
+    (Green, Advance) => Orange {},
+    (Green, PassCar) => [Green, Orange] as PassCarOutcome,
+    (Orange, after(Duration::from_secs(5))) => Red |_| (Red {}, vec![Command::StopTraffic]),
+    (Red, Advance) => Green
+  ]
+);
+
+impl Green {
+  pub fn on_pass_car(self, input: PassCar) -> (PassCarOutcome, Vec<Command>) {
+    let count = self.count + input.count;
+    if count >= 10 {
+      (PassCarOutcome::Orange(Orange {}), vec![Command::Beep])
+    } else {
+      (PassCarOutcome::Green(Green { count }), Vec::new())
+    }
+  }
+}
+
+impl Red {
+  pub fn on_advance(self, _: Advance) -> (Green, Vec<Command>) {
+    (Green { count: 0 }, vec![Command::Beep, Command::Beep])
+  }
+}
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Protocol {
+    Idle,
+    Connecting,
+    Handshake,
+    Connected,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Connect;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Ack;
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum Bytes {
+  Syn,
+  Ack,
+  Ready,
+}
+
+transitions!(Protocol, runner, output = Bytes,
+  [
+    (Idle, Connect) => Connecting |_, _, queue| {
+      queue.post(Ack);
+      (Connecting {}, vec![Bytes::Syn])
+    },
+    (Connecting, Ack) => Handshake |_, _, _| (Handshake {}, vec![Bytes::Ack]),
+    (Handshake, _) => Connected |_| (Connected {}, vec![Bytes::Ready])
+  ]
+);
+
+#[test]
+fn outputs() {
+  let (t, output) = Traffic::green(0).on_pass_car(PassCar { count: 4 });
+  assert_eq!(t, Traffic::green(4));
+  assert!(output.is_empty());
+
+  let (t, output) = t.on_pass_car(PassCar { count: 6 });
+  assert_eq!(t, Traffic::orange());
+  assert_eq!(output, vec![Command::Beep]);
+
+  let (t, output) = t.on_advance(Advance);
+  assert_eq!(t, Traffic::error());
+  assert!(output.is_empty());
+
+  let (t, output) = Traffic::red().on_advance(Advance);
+  assert_eq!(t, Traffic::green(0));
+  assert_eq!(output, vec![Command::Beep, Command::Beep]);
+}
+
+#[test]
+fn timer_outputs() {
+  let clock = TrafficManualClock::new();
+  let mut timer = TrafficTimer::new(Traffic::green(0), &clock);
+
+  let output = timer.update(|t| t.on_advance(Advance));
+  assert_eq!(timer.state(), &Traffic::orange());
+  assert!(output.is_empty());
+
+  clock.advance(Duration::from_secs(5));
+  assert_eq!(timer.tick(), vec![Command::StopTraffic]);
+  assert_eq!(timer.state(), &Traffic::red());
+}
+
+#[test]
+fn runner_outputs() {
+  let mut runner = ProtocolRunner::new(Protocol::idle());
+  runner.post(Connect);
+  runner.run_until_idle();
+
+  assert_eq!(runner.state(), &Protocol::connected());
+  assert_eq!(runner.take_outputs(), vec![Bytes::Syn, Bytes::Ack, Bytes::Ready]);
+  assert!(runner.take_outputs().is_empty());
+}
+
+#[test]
+fn dot_labels() {
+  // the edges of the handlers are labeled with the output type
+  let dot = std::fs::read_to_string("target/protocol.dot").unwrap();
+  assert!(dot.contains("Idle -> Connecting [ label = \"Connect / Bytes\" ];"));
+  assert!(dot.contains("Connecting -> Handshake [ label = \"Ack / Bytes\" ];"));
+  assert!(dot.contains("Handshake -> Connected [ label = \"/ Bytes\" ];"));
+}
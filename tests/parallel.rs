@@ -0,0 +1,99 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Playback {
+    Stopped,
+    Playing,
+    Paused,
+  }
+);
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Volume {
+    Normal,
+    Muted,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Play;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Pause;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Stop;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Mute;
+
+transitions!(Playback,
+  [
+    (Stopped, Play) => Playing |_, _| Playing {},
+    (Paused, Play) => Playing |_, _| Playing {},
+    (Playing, Pause) => Paused |_, _| Paused {},
+    (Playing, Stop) => Stopped |_, _| Stopped {},
+    (Paused, Stop) => Stopped |_, _| Stopped {}
+  ]
+);
+
+transitions!(Volume,
+  [
+    (Normal, Mute) => Muted |_, _| Muted {},
+    (Muted, Mute) => Normal |_, _| Normal {},
+    (Muted, Stop) => Normal |_, _| Normal {}
+  ]
+);
+
+parallel!(Player = Playback & Volume);
+
+#[test]
+fn broadcast() {
+  let mut p = Player::new(Playback::stopped(), Volume::normal());
+
+  p = p.on_play(Play);
+  assert_eq!(p.playback, Playback::playing());
+  assert_eq!(p.volume, Volume::normal());
+
+  p = p.on_mute(Mute);
+  assert_eq!(p.playback, Playback::playing());
+  assert_eq!(p.volume, Volume::muted());
+
+  p = p.on_stop(Stop);
+  assert_eq!(p.playback, Playback::stopped());
+  assert_eq!(p.volume, Volume::normal());
+}
+
+#[test]
+fn ignored_by_current_state() {
+  let mut p = Player::new(Playback::stopped(), Volume::normal());
+
+  // neither region has a transition for these in its current state
+  p = p.on_stop(Stop);
+  assert_eq!(p.playback, Playback::stopped());
+  assert_eq!(p.volume, Volume::normal());
+
+  p = p.on_pause(Pause);
+  assert_eq!(p.playback, Playback::stopped());
+}
+
+#[test]
+fn dispatch() {
+  let messages: Vec<PlayerMessages> = vec![
+    Play.into(),
+    Mute.into(),
+    Pause.into(),
+    Play.into(),
+    Mute.into(),
+  ];
+
+  let p = messages.into_iter().fold(
+    Player::new(Playback::stopped(), Volume::normal()),
+    |p, message| p.dispatch(message),
+  );
+  assert_eq!(p.playback, Playback::playing());
+  assert_eq!(p.volume, Volume::normal());
+}
@@ -0,0 +1,101 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct PassCar { count: u8 }
+
+transitions!(Traffic, runner,
+  [
+    (Green, Advance) => Orange {},
+    (Green, PassCar) => Green |state, input| Green { count: state.count + input.count },
+    (Orange, Advance) => Red |_, _, queue| {
+      // the light turns back to green on its own
+      queue.post(Advance);
+      Red {}
+    },
+    (Orange, PassCar) => defer,
+    (Red, Advance) => Green |_| Green { count: 0 },
+    (Red, PassCar) => defer
+  ]
+);
+
+#[test]
+fn self_posted() {
+  let mut runner = TrafficRunner::new(Traffic::green(0));
+  runner.post(Advance);
+  runner.post(Advance);
+  runner.run_until_idle();
+
+  assert_eq!(runner.state(), &Traffic::green(0));
+}
+
+#[test]
+fn deferred() {
+  let mut runner = TrafficRunner::new(Traffic::orange());
+  runner.post(PassCar { count: 1 });
+  runner.post(PassCar { count: 2 });
+  runner.run_until_idle();
+
+  assert_eq!(runner.state(), &Traffic::orange());
+  assert_eq!(runner.deferred().len(), 2);
+
+  // Orange -> Red posts Advance, and the cars pass once back on Green
+  runner.post(Advance);
+  runner.run_until_idle();
+
+  assert_eq!(runner.state(), &Traffic::green(3));
+  assert!(runner.deferred().is_empty());
+}
+
+#[test]
+fn dispatch() {
+  let mut queue = TrafficQueue::default();
+  let t = Traffic::orange().dispatch(TrafficMessages::Advance(Advance), &mut queue);
+
+  assert_eq!(t, Traffic::red());
+  assert!(!queue.is_empty());
+  assert!(t.defers(&PassCar { count: 1 }.into()));
+}
+
+#[test]
+fn dispatch_deferred() {
+  // the deferred message waits in the queue until the state changes
+  let mut queue = TrafficQueue::default();
+  let t = Traffic::orange().dispatch(PassCar { count: 1 }.into(), &mut queue);
+
+  assert_eq!(t, Traffic::orange());
+  assert!(queue.is_empty());
+  assert_eq!(queue.deferred().len(), 1);
+
+  // back in the queue, before the Advance posted by the handler
+  let t = t.dispatch(Advance.into(), &mut queue);
+  assert_eq!(t, Traffic::red());
+  assert!(queue.deferred().is_empty());
+  assert!(!queue.is_empty());
+}
+
+#[test]
+fn runner_wrappers() {
+  let mut runner = TrafficRunner::new(Traffic::orange());
+  runner.on_pass_car(PassCar { count: 1 });
+  assert_eq!(runner.deferred().len(), 1);
+
+  // handled right away, the posted Advance waits in the queue
+  runner.on_advance(Advance);
+  assert_eq!(runner.state(), &Traffic::red());
+
+  runner.run_until_idle();
+  assert_eq!(runner.state(), &Traffic::green(1));
+}
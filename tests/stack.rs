@@ -0,0 +1,92 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red,
+    Dialog { text: String },
+    Confirm,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Open { text: String }
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Ask;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Close;
+
+transitions!(Traffic,
+  [
+    (Green, Advance) => Orange {},
+    (Orange, Advance) => Red {},
+    (Red, Advance) => Green { count: 0 },
+    (Green, Open) => push Dialog |_, input| Dialog { text: input.text },
+    (Red, Open) => push Dialog |_, input| Dialog { text: input.text },
+    (Dialog, Ask) => push Confirm {},
+    (Dialog, Close) => pop,
+    (Confirm, Close) => pop
+  ]
+);
+
+fn open(text: &str) -> Open {
+  Open { text: text.to_string() }
+}
+
+#[test]
+fn push_and_pop() {
+  let mut stack = TrafficStack::new(Traffic::green(3));
+  assert_eq!(stack.depth(), 1);
+
+  stack.on_open(open("hello"));
+  assert_eq!(stack.top(), &Traffic::dialog("hello".to_string()));
+  assert_eq!(stack.states(), &[Traffic::green(3), Traffic::dialog("hello".to_string())][..]);
+
+  stack.on_ask(Ask);
+  assert_eq!(stack.top(), &Traffic::confirm());
+  assert_eq!(stack.depth(), 3);
+
+  stack.on_close(Close);
+  assert_eq!(stack.top(), &Traffic::dialog("hello".to_string()));
+
+  stack.on_close(Close);
+  assert_eq!(stack.top(), &Traffic::green(3));
+  assert_eq!(stack.depth(), 1);
+}
+
+#[test]
+fn top_transitions() {
+  let mut stack = TrafficStack::new(Traffic::green(0));
+  stack.dispatch(TrafficMessages::Advance(Advance));
+  stack.dispatch(TrafficMessages::Advance(Advance));
+  assert_eq!(stack.top(), &Traffic::red());
+
+  stack.dispatch(TrafficMessages::Open(open("stop")));
+  // the dialog has no transition for `Advance`
+  stack.dispatch(TrafficMessages::Advance(Advance));
+  assert_eq!(stack.states(), &[Traffic::red(), Traffic::error()][..]);
+}
+
+#[test]
+fn pop_last_state() {
+  let mut stack = TrafficStack::new(Traffic::dialog("alone".to_string()));
+  stack.on_close(Close);
+  assert_eq!(stack.top(), &Traffic::error());
+  assert_eq!(stack.depth(), 1);
+}
+
+#[test]
+fn machine_alone() {
+  // without the stack, pushing enters the state and nothing can be popped
+  let t = Traffic::green(0).on_open(open("hello"));
+  assert_eq!(t, Traffic::dialog("hello".to_string()));
+  assert_eq!(t.on_close(Close), Traffic::error());
+}
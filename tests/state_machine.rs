@@ -0,0 +1,84 @@
+#[macro_use]
+extern crate machine;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct PassCar { count: u8 }
+
+state_machine! {
+  #[derive(Clone,Debug,PartialEq)]
+  enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red,
+  }
+
+  transitions(typed) {
+    (Green, Advance) => Orange {},
+    (Green, PassCar) => [Green, Orange],
+    (Orange, Advance) => Red {},
+    (Red, Advance) => Green { count: 0 }
+  }
+
+  methods {
+    Green => get count: u8,
+    [Green, Orange, Red] => fn can_pass(&self) -> bool
+  }
+}
+
+impl Green {
+  pub fn on_pass_car(self, input: PassCar) -> Traffic {
+    let count = self.count + input.count;
+    if count >= 10 {
+      Traffic::orange()
+    } else {
+      Traffic::green(count)
+    }
+  }
+
+  pub fn can_pass(&self) -> bool {
+    true
+  }
+}
+
+impl Orange {
+  pub fn can_pass(&self) -> bool {
+    false
+  }
+}
+
+impl Red {
+  pub fn can_pass(&self) -> bool {
+    false
+  }
+}
+
+state_machine! {
+  #[derive(Clone,Debug,PartialEq)]
+  enum Switch {
+    Off,
+    On,
+  }
+}
+
+#[test]
+fn combined() {
+  let t = Traffic::green(0).on_pass_car(PassCar { count: 4 });
+  assert_eq!(t.count(), Some(&4));
+  assert_eq!(t.can_pass(), Some(true));
+
+  let t = t.on_advance(Advance);
+  assert_eq!(t, Traffic::orange());
+  assert_eq!(t.count(), None);
+  assert_eq!(t.can_pass(), Some(false));
+
+  let red = TrafficTyped::new(Orange {}).advance(Advance);
+  assert_eq!(Traffic::from(red), Traffic::red());
+}
+
+#[test]
+fn machine_only() {
+  assert_eq!(Switch::off(), Switch::Off(Off {}));
+}
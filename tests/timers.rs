@@ -0,0 +1,64 @@
+#[macro_use]
+extern crate machine;
+
+use std::time::Duration;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+transitions!(Traffic,
+  [
+    (Green, Advance) => Orange {},
+    (Orange, after(Duration::from_secs(5))) => Red {},
+    (Red, after(Duration::from_secs(30))) => Green |_| Green { count: 0 }
+  ]
+);
+
+#[test]
+fn deadlines() {
+  let clock = TrafficManualClock::new();
+  let entered_at = clock.now();
+
+  assert_eq!(Traffic::green(0).next_deadline(entered_at), None);
+  assert_eq!(Traffic::orange().next_deadline(entered_at), Some(entered_at + Duration::from_secs(5)));
+
+  let t = Traffic::orange().on_tick(entered_at, entered_at + Duration::from_secs(4));
+  assert_eq!(t, Traffic::orange());
+  let t = t.on_tick(entered_at, entered_at + Duration::from_secs(5));
+  assert_eq!(t, Traffic::red());
+}
+
+#[test]
+fn timer() {
+  let clock = TrafficManualClock::new();
+  let mut timer = TrafficTimer::new(Traffic::green(0), &clock);
+
+  clock.advance(Duration::from_secs(60));
+  timer.tick();
+  assert_eq!(timer.state(), &Traffic::green(0));
+
+  timer.update(|t| t.on_advance(Advance));
+  assert_eq!(timer.state(), &Traffic::orange());
+
+  clock.advance(Duration::from_secs(3));
+  timer.tick();
+  assert_eq!(timer.state(), &Traffic::orange());
+
+  clock.advance(Duration::from_secs(2));
+  timer.tick();
+  assert_eq!(timer.state(), &Traffic::red());
+  assert_eq!(timer.next_deadline(), Some(clock.now() + Duration::from_secs(30)));
+
+  clock.advance(Duration::from_secs(30));
+  timer.tick();
+  assert_eq!(timer.into_state(), Traffic::green(0));
+}
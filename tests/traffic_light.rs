@@ -3,7 +3,7 @@ extern crate machine;
 
 machine!(
   #[derive(Clone,Debug,PartialEq)]
-  enum TrafficLight {
+  pub enum TrafficLight {
     Green { count: u8 },
     Orange,
     Red,
@@ -0,0 +1,59 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct PassCar { count: u8 }
+
+transitions!(Traffic, typed,
+  [
+    (Green, Advance) => Orange {},
+    (Green, PassCar) => [Green, Orange],
+    (Orange, Advance) => Red {},
+    (Red, Advance) => Green { count: 0 }
+  ]
+);
+
+impl Green {
+  pub fn on_pass_car(self, input: PassCar) -> Traffic {
+    let count = self.count + input.count;
+    if count >= 10 {
+      Traffic::orange()
+    } else {
+      Traffic::green(count)
+    }
+  }
+}
+
+#[test]
+fn typed_transitions() {
+  let green = TrafficTyped::new(Green { count: 3 });
+  assert_eq!(green.state().count, 3);
+
+  let orange: TrafficTyped<Orange> = green.advance(Advance);
+  let red: TrafficTyped<Red> = orange.advance(Advance);
+  let green: TrafficTyped<Green> = red.advance(Advance);
+  assert_eq!(green.into_state().count, 0);
+}
+
+#[test]
+fn into_dynamic() {
+  let orange = TrafficTyped::new(Green { count: 3 }).advance(Advance);
+  let t: Traffic = orange.into();
+  assert_eq!(t, Traffic::orange());
+
+  // transitions with several end states are only on the enum
+  let t: Traffic = TrafficTyped::new(Green { count: 3 }).into();
+  assert_eq!(t.on_pass_car(PassCar { count: 1 }), Traffic::green(4));
+}
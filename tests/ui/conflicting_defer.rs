@@ -0,0 +1,21 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  enum Traffic {
+    Orange,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct PassCar;
+
+transitions!(Traffic, runner,
+  [
+    (Orange, PassCar) => defer,
+    (Orange, PassCar) => Red
+  ]
+);
+
+fn main() {}
@@ -0,0 +1,22 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  enum Traffic {
+    Green,
+    Orange,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+transitions!(Traffic,
+  [
+    (Green, Advance) => Orange,
+    (Green, Advance) => Red
+  ]
+);
+
+fn main() {}
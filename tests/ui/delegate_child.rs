@@ -0,0 +1,41 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Handshake {
+    Hello,
+    Done,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct HandshakeMsg;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Disconnect;
+
+// no `final_states`, and the runner gives the child's methods a queue
+transitions!(Handshake, runner,
+  [
+    (Hello, HandshakeMsg) => Done
+  ]
+);
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Session {
+    Connecting { handshake: Handshake },
+    Established,
+  }
+);
+
+transitions!(Session,
+  [
+    (Connecting, HandshakeMsg) => delegate handshake,
+    (Connecting, Disconnect) => delegate handshake,
+    (Connecting, on_final(handshake)) => Established
+  ]
+);
+
+fn main() {}
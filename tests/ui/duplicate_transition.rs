@@ -0,0 +1,21 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  enum Traffic {
+    Green,
+    Orange,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+transitions!(Traffic,
+  [
+    (Green, Advance) => Orange,
+    (Green, Advance) => Orange
+  ]
+);
+
+fn main() {}
@@ -0,0 +1,26 @@
+#[macro_use]
+extern crate machine;
+
+pub struct Counter {
+  presses: u32,
+}
+
+machine!(
+  context = Counter,
+  enum Button {
+    Up,
+    Down,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Press;
+
+#[handlers(Button)]
+impl Up {
+  pub fn on_press(self, _: &mut String, _: Press) -> Down {
+    Down {}
+  }
+}
+
+fn main() {}
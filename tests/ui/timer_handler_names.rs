@@ -0,0 +1,25 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  enum Traffic {
+    Green,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Timeout;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Tick;
+
+transitions!(Traffic,
+  [
+    (Green, after(::std::time::Duration::from_secs(30))) => Red,
+    (Red, Timeout) => Green,
+    (Red, Tick) => Red
+  ]
+);
+
+fn main() {}
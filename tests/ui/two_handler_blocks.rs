@@ -0,0 +1,28 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  enum Traffic {
+    Green,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+#[handlers(Traffic)]
+impl Green {
+  pub fn on_advance(self, _: Advance) -> Red {
+    Red {}
+  }
+}
+
+#[handlers(Traffic)]
+impl Red {
+  pub fn on_advance(self, _: Advance) -> Green {
+    Green {}
+  }
+}
+
+fn main() {}
@@ -0,0 +1,26 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Player {
+    Paused,
+    #[superstate(Active)]
+    Playing,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Pause;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Resume;
+
+transitions!(Player, typed,
+  [
+    (Active, Pause) => Paused,
+    (Paused, Resume) => Active::history
+  ]
+);
+
+fn main() {}
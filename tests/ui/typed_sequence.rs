@@ -0,0 +1,32 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Stop;
+
+transitions!(Traffic, typed,
+  [
+    (Green, Advance) => Orange {},
+    (Orange, Advance) => Red {},
+    (Orange, Stop) => Red {},
+    (Red, Advance) => Green { count: 0 }
+  ]
+);
+
+fn main() {
+  let green = TrafficTyped::new(Green { count: 0 });
+  // only `Orange` accepts `Stop`
+  let _ = green.stop(Stop);
+}
@@ -0,0 +1,21 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  enum Traffic {
+    Green { count: u8 },
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+transitions!(Traffic,
+  [
+    (Green, Advance) => Red {},
+    (Red, Advance) => Green {}
+  ]
+);
+
+fn main() {}
@@ -0,0 +1,17 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  enum Traffic {
+    Green { count: u8 },
+    Red,
+  }
+);
+
+methods!(Traffic,
+  [
+    Red => get count: u8
+  ]
+);
+
+fn main() {}
@@ -0,0 +1,20 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  enum Traffic {
+    Green,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+transitions!(Traffic,
+  [
+    (Green, Advance) => Yellow
+  ]
+);
+
+fn main() {}
@@ -0,0 +1,51 @@
+#[macro_use]
+extern crate machine;
+
+// without `#[macro_use]`, the description of the machine is not in scope
+// after this module
+mod states {
+  machine!(
+    #[derive(Clone,Debug,PartialEq)]
+    pub enum Door {
+      Open,
+      Closed { pub locked: bool },
+    }
+  );
+}
+
+use states::*;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Push;
+
+transitions!(Door, unchecked,
+  [
+    (Open, Push) => Closed,
+    (Closed, Push) => Open
+  ]
+);
+
+methods!(Door, unchecked,
+  [
+    Closed => get locked: bool
+  ]
+);
+
+impl Open {
+  pub fn on_push(self, _: Push) -> Closed {
+    Closed { locked: true }
+  }
+}
+
+impl Closed {
+  pub fn on_push(self, _: Push) -> Open {
+    Open {}
+  }
+}
+
+#[test]
+fn table_out_of_scope() {
+  let door = Door::open().on_push(Push);
+  assert_eq!(door.locked(), Some(&true));
+  assert_eq!(door.on_push(Push), Door::open());
+}
@@ -0,0 +1,69 @@
+#[macro_use]
+extern crate machine;
+
+mod lights {
+  machine!(
+    #[derive(Clone,Debug,PartialEq)]
+    pub(crate) enum Traffic {
+      #[visibility(pub(crate))]
+      Green { pub(crate) count: u8 },
+      Orange,
+    }
+  );
+
+  #[derive(Clone,Debug,PartialEq)]
+  pub struct Advance;
+
+  transitions!(Traffic, runner,
+    [
+      (Green, Advance) => Orange {},
+      (Orange, Advance) => Green { count: 0 }
+    ]
+  );
+
+  methods!(Traffic,
+    [
+      Green => pub(crate) get count: u8,
+      Green => pub(crate) set count: u8
+    ]
+  );
+
+  // private to this module
+  machine!(
+    #[machine(mod = inner)]
+    #[derive(Clone,Debug,PartialEq)]
+    enum Door {
+      Open,
+      Closed { locked: bool },
+    }
+  );
+
+  transitions!(Door, [(Open, Advance) => Closed { locked: false }]);
+
+  pub fn close_door() -> bool {
+    match Door::open().on_advance(Advance) {
+      Door::Closed(inner::Closed { locked }) => !locked,
+      _ => false,
+    }
+  }
+}
+
+use lights::{Advance, Traffic, TrafficMessages, TrafficRunner};
+
+#[test]
+fn crate_visible_machine() {
+  let mut runner = TrafficRunner::new(Traffic::green(3));
+  runner.post(TrafficMessages::Advance(Advance));
+  runner.post(Advance);
+  runner.run_until_idle();
+
+  let mut t = runner.into_state();
+  assert_eq!(t.count(), Some(&0));
+  *t.count_mut().unwrap() = 2;
+  assert_eq!(t, Traffic::Green(lights::Green { count: 2 }));
+}
+
+#[test]
+fn private_machine() {
+  assert!(lights::close_door());
+}